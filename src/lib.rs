@@ -1,10 +1,20 @@
 pub mod lm;
 pub mod rt;
 pub mod shapes;
+pub mod utils;
 
 pub use crate::lm::math::*;
+pub use crate::lm::quaternion::Quaternion;
 pub use crate::lm::transform::*;
 pub use crate::rt::ray::{Intersection, Ray};
 pub use crate::shapes::{
-    mesh::Mesh, plane::Plane, shape::Shape, sphere::Sphere, triangle::Triangle,
+    bvh::Bvh,
+    mesh::{Mesh, MeshData, MeshError, MeshTriangle},
+    plane::Plane,
+    primitive::Primitive,
+    quad::Quad,
+    scene::Scene,
+    shape::{Shape, ShapeSample},
+    sphere::Sphere,
+    triangle::Triangle,
 };
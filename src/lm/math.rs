@@ -3,6 +3,11 @@ use std::ops::{Add, Div, Index, IndexMut, Mul, Neg, Sub};
 
 pub type Float = f64;
 
+pub const fn gamma(n: i32) -> Float {
+    let e = Float::EPSILON * 0.5 * n as Float;
+    e / (1.0 - e)
+}
+
 pub fn sub_mul_pair(a: Float, b: Float, c: Float, d: Float) -> Float {
     let cd = c * d;
     let sub_mul_pair = a.mul_add(b, -cd);
@@ -29,14 +34,8 @@ impl<const N: usize> SquareMatrix<N> {
 
     pub fn identity() -> SquareMatrix<N> {
         let mut m = [[0.0; N]; N];
-        for i in 0..N {
-            for j in 0..N {
-                if i == j {
-                    m[i][j] = 1.0;
-                } else {
-                    m[i][j] = 0.0;
-                }
-            }
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
         }
 
         SquareMatrix::<N> { m }
@@ -344,6 +343,7 @@ impl<const N: usize> Mul<&SquareMatrix<N>> for Float {
 impl<const N: usize> Div<Float> for SquareMatrix<N> {
     type Output = SquareMatrix<N>;
 
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn div(self, rhs: Float) -> Self::Output {
         let recip = rhs.recip();
         let mut m = [[0.0; N]; N];
@@ -360,6 +360,7 @@ impl<const N: usize> Div<Float> for SquareMatrix<N> {
 impl<const N: usize> Div<Float> for &SquareMatrix<N> {
     type Output = SquareMatrix<N>;
 
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn div(self, rhs: Float) -> Self::Output {
         let inv = rhs.recip();
         let mut m = [[0.0; N]; N];
@@ -461,6 +462,12 @@ impl<const N: usize> Vector<N> {
     pub fn normalize(&self) -> Vector<N> {
         self / self.magnitude()
     }
+
+    pub fn abs(&self) -> Vector<N> {
+        Vector::<N> {
+            v: self.v.map(Float::abs),
+        }
+    }
 }
 
 impl Vector<3> {
@@ -473,6 +480,62 @@ impl Vector<3> {
             ],
         }
     }
+
+    // Builds two vectors completing an orthonormal basis with a normalized `self`
+    // (Duff et al., "Building an Orthonormal Basis, Revisited").
+    pub fn coordinate_system(&self) -> (Vector<3>, Vector<3>) {
+        let sign = 1.0_f64.copysign(self[2]);
+        let a = -1.0 / (sign + self[2]);
+        let b = self[0] * self[1] * a;
+        (
+            Vector::<3>::new([
+                1.0 + sign * self[0] * self[0] * a,
+                sign * b,
+                -sign * self[0],
+            ]),
+            Vector::<3>::new([b, sign + self[1] * self[1] * a, -self[1]]),
+        )
+    }
+}
+
+// Orthonormal basis, directions in local coordinates have the normal along +z.
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub x: Vector<3>,
+    pub y: Vector<3>,
+    pub z: Vector<3>,
+}
+
+impl Frame {
+    pub fn from_z(z: &Vector<3>) -> Frame {
+        let z = z.normalize();
+        let (x, y) = z.coordinate_system();
+        Frame { x, y, z }
+    }
+
+    // Keeps `z` and makes `x` orthogonal to it, falling back to an arbitrary
+    // tangent when they are parallel.
+    pub fn from_xz(x: &Vector<3>, z: &Vector<3>) -> Frame {
+        let z = z.normalize();
+        let x = *x - z * x.dot(&z);
+        if x.magnitude() == 0.0 || !x.magnitude().is_finite() {
+            return Frame::from_z(&z);
+        }
+        let x = x.normalize();
+        Frame {
+            x,
+            y: z.cross(&x),
+            z,
+        }
+    }
+
+    pub fn to_local(&self, v: &Vector<3>) -> Vector<3> {
+        Vector::<3>::new([v.dot(&self.x), v.dot(&self.y), v.dot(&self.z)])
+    }
+
+    pub fn from_local(&self, v: &Vector<3>) -> Vector<3> {
+        self.x * v[0] + self.y * v[1] + self.z * v[2]
+    }
 }
 
 impl<const N: usize> Add<Vector<N>> for Vector<N> {
@@ -792,6 +855,96 @@ impl<const N: usize> Bound<N> {
 
         true
     }
+
+    pub const fn empty() -> Bound<N> {
+        let b = [(Float::INFINITY, Float::NEG_INFINITY); N];
+        Bound::<N> { b }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.b
+            .iter()
+            .all(|(min, max)| min.is_finite() && max.is_finite())
+    }
+
+    pub fn union(&self, rhs: &Bound<N>) -> Bound<N> {
+        let mut b = [(0.0, 0.0); N];
+        for (i, v) in b.iter_mut().enumerate() {
+            *v = (self.b[i].0.min(rhs.b[i].0), self.b[i].1.max(rhs.b[i].1));
+        }
+        Bound::<N> { b }
+    }
+
+    pub fn union_point(&self, p: &Vector<N>) -> Bound<N> {
+        let mut b = [(0.0, 0.0); N];
+        for (i, v) in b.iter_mut().enumerate() {
+            *v = (self.b[i].0.min(p[i]), self.b[i].1.max(p[i]));
+        }
+        Bound::<N> { b }
+    }
+
+    pub fn diagonal(&self) -> Vector<N> {
+        let mut v = [0.0; N];
+        for (i, d) in v.iter_mut().enumerate() {
+            *d = self.b[i].1 - self.b[i].0;
+        }
+        Vector::<N> { v }
+    }
+
+    pub fn centroid(&self) -> Vector<N> {
+        let mut v = [0.0; N];
+        for (i, c) in v.iter_mut().enumerate() {
+            *c = 0.5 * self.b[i].0 + 0.5 * self.b[i].1;
+        }
+        Vector::<N> { v }
+    }
+
+    pub fn max_extent(&self) -> usize {
+        let d = self.diagonal();
+        (0..N).fold(0, |axis, i| if d[i] > d[axis] { i } else { axis })
+    }
+
+    // Disjoint bounds produce an inverted, empty result.
+    pub fn intersection(&self, rhs: &Bound<N>) -> Bound<N> {
+        let mut b = [(0.0, 0.0); N];
+        for (i, v) in b.iter_mut().enumerate() {
+            *v = (self.b[i].0.max(rhs.b[i].0), self.b[i].1.min(rhs.b[i].1));
+        }
+        Bound::<N> { b }
+    }
+
+    pub fn overlaps(&self, rhs: &Bound<N>) -> bool {
+        (0..N).all(|i| self.b[i].0 <= rhs.b[i].1 && self.b[i].1 >= rhs.b[i].0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.b.iter().any(|(min, max)| min > max)
+    }
+}
+
+impl Bound<3> {
+    pub fn surface_area(&self) -> Float {
+        let d = self.diagonal();
+        if d[0] < 0.0 || d[1] < 0.0 || d[2] < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d[0] * d[1] + d[0] * d[2] + d[1] * d[2])
+    }
+
+    // Returns the centre and radius of a sphere enclosing the bounds. Unbounded
+    // boxes get an infinite radius.
+    pub fn bounding_sphere(&self) -> (Vector<3>, Float) {
+        let center = self.centroid();
+        let radius = if self.is_empty() {
+            0.0
+        } else if !self.is_finite() {
+            Float::INFINITY
+        } else {
+            (Vector::<3>::new([self.b[0].1, self.b[1].1, self.b[2].1]) - center)
+                .magnitude()
+        };
+        (center, radius)
+    }
 }
 
 impl<const N: usize> Index<usize> for Bound<N> {
@@ -808,3 +961,109 @@ impl<const N: usize> IndexMut<usize> for Bound<N> {
     }
 }
 pub type Interval = Bound<1>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::ray::Ray;
+
+    fn unit() -> Bound<3> {
+        Bound::new([(0.0, 1.0), (0.0, 2.0), (0.0, 4.0)])
+    }
+
+    #[test]
+    fn bound_union_and_intersection() {
+        let a = unit();
+        let b = Bound::new([(0.5, 3.0), (-1.0, 1.0), (1.0, 2.0)]);
+        assert_eq!(a.union(&b).b, [(0.0, 3.0), (-1.0, 2.0), (0.0, 4.0)]);
+        assert_eq!(a.intersection(&b).b, [(0.5, 1.0), (0.0, 1.0), (1.0, 2.0)]);
+        assert!(a.overlaps(&b) && b.overlaps(&a));
+
+        let disjoint = Bound::new([(2.0, 3.0), (0.0, 1.0), (0.0, 1.0)]);
+        assert!(!a.overlaps(&disjoint));
+        assert!(a.intersection(&disjoint).is_empty());
+        // Touching faces count as overlapping.
+        let touching = Bound::new([(1.0, 3.0), (0.0, 1.0), (0.0, 1.0)]);
+        assert!(a.overlaps(&touching));
+
+        let empty = Bound::<3>::empty();
+        assert_eq!(a.union(&empty).b, a.b);
+        assert!(a.intersection(&empty).is_empty());
+        assert!(!a.overlaps(&empty) && !empty.overlaps(&empty));
+
+        let all = Bound::<3>::all();
+        assert_eq!(a.union(&all).b, all.b);
+        assert_eq!(a.intersection(&all).b, a.b);
+        assert!(a.overlaps(&all));
+    }
+
+    #[test]
+    fn bound_measures() {
+        let a = unit();
+        assert_eq!(a.diagonal().v, [1.0, 2.0, 4.0]);
+        assert_eq!(a.centroid().v, [0.5, 1.0, 2.0]);
+        assert_eq!(a.max_extent(), 2);
+        assert_eq!(a.surface_area(), 2.0 * (2.0 + 4.0 + 8.0));
+        let (center, radius) = a.bounding_sphere();
+        assert_eq!(center.v, [0.5, 1.0, 2.0]);
+        assert_eq!(radius, (0.25 + 1.0 + 4.0 as Float).sqrt());
+
+        // Ties keep the first axis and a point has no extent.
+        let cube = Bound::new([(0.0, 1.0); 3]);
+        assert_eq!(cube.max_extent(), 0);
+        let point = Bound::<3>::empty().union_point(&Vector3::new([1.0, 2.0, 3.0]));
+        assert_eq!(point.surface_area(), 0.0);
+        assert_eq!(point.bounding_sphere().1, 0.0);
+
+        let empty = Bound::<3>::empty();
+        assert_eq!(empty.surface_area(), 0.0);
+        assert_eq!(empty.bounding_sphere().1, 0.0);
+
+        let half = Bound::new([(0.0, 1.0), (0.0, Float::INFINITY), (0.0, 1.0)]);
+        assert_eq!(half.max_extent(), 1);
+        assert_eq!(half.surface_area(), Float::INFINITY);
+        assert_eq!(half.bounding_sphere().1, Float::INFINITY);
+        assert_eq!(Bound::<3>::all().bounding_sphere().1, Float::INFINITY);
+    }
+
+    #[test]
+    fn bound_intersect_ray() {
+        let bounds = Bound::new([(-1.0, 1.0), (-1.0, 1.0), (2.0, 4.0)]);
+        let unbounded = Interval::new([(0.0, Float::INFINITY)]);
+        let ray = Ray::new(Vector3::new([0.5, 0.0, 0.0]), Vector3::new([0.0, 0.0, 2.0]));
+
+        let b = bounds.intersect_ray(&ray, &unbounded).unwrap();
+        assert_eq!(b[0].0, 1.0);
+        assert!(b[0].1 >= 2.0 && b[0].1 < 2.0 + 1E-12);
+        let clipped = bounds.intersect_ray(&ray, &Interval::new([(1.5, 1.75)]));
+        assert_eq!(clipped.unwrap()[0], (1.5, 1.75));
+        assert!(bounds
+            .intersect_ray(&ray, &Interval::new([(0.0, 0.5)]))
+            .is_none());
+
+        // Axis-parallel rays in the plane of a face still reach the box, those
+        // beside it do not.
+        let z = Vector3::new([0.0, 0.0, 1.0]);
+        let grazing = Ray::new(Vector3::new([1.0, -1.0, 0.0]), z);
+        assert!(bounds.intersect_ray(&grazing, &unbounded).is_some());
+        let outside = Ray::new(Vector3::new([1.5, 0.0, 0.0]), z);
+        assert!(bounds.intersect_ray(&outside, &unbounded).is_none());
+        let x = Ray::new(
+            Vector3::new([-5.0, 0.0, 3.0]),
+            Vector3::new([1.0, 0.0, 0.0]),
+        );
+        assert_eq!(bounds.intersect_ray(&x, &unbounded).unwrap()[0].0, 4.0);
+        let away = Ray::new(Vector3::new([0.0, 0.0, 5.0]), z);
+        assert!(bounds.intersect_ray(&away, &unbounded).is_none());
+
+        // A ray starting inside enters at the start of the interval.
+        let inside = Ray::new(Vector3::new([0.0, 0.0, 3.0]), -z);
+        assert_eq!(bounds.intersect_ray(&inside, &unbounded).unwrap()[0].0, 0.0);
+
+        assert!(Bound::<3>::empty()
+            .intersect_ray(&ray, &unbounded)
+            .is_none());
+        let all = Bound::<3>::all().intersect_ray(&ray, &unbounded).unwrap();
+        assert_eq!(all[0], (0.0, Float::INFINITY));
+    }
+}
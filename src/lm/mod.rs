@@ -1,4 +1,6 @@
 pub mod math;
 pub mod primes;
+pub mod quaternion;
 pub mod rng;
+pub mod sampling;
 pub mod transform;
@@ -1,7 +1,8 @@
+use crate::lm::rng::{mix_bits, permutation_element, ONE_MINUS_EPSILON};
 use crate::Float;
 
-const _PRIME_LUT_LEN: usize = 1000;
-const _PRIMES: [Float; _PRIME_LUT_LEN] = [
+pub const PRIME_TABLE_SIZE: usize = 1000;
+pub const PRIMES: [Float; PRIME_TABLE_SIZE] = [
     2.0, 3.0, 5.0, 7.0, 11.0, //,Subsequent,prime,numbers,
     13.0, 17.0, 19.0, 23.0, 29.0, 31.0, 37.0, 41.0, 43.0, 47.0, 53.0, 59.0, 61.0, 67.0,
     71.0, 73.0, 79.0, 83.0, 89.0, 97.0, 101.0, 103.0, 107.0, 109.0, 113.0, 127.0, 131.0,
@@ -101,3 +102,173 @@ const _PRIMES: [Float; _PRIME_LUT_LEN] = [
     7789.0, 7793.0, 7817.0, 7823.0, 7829.0, 7841.0, 7853.0, 7867.0, 7873.0, 7877.0,
     7879.0, 7883.0, 7901.0, 7907.0, 7919.0,
 ];
+
+pub fn prime(index: usize) -> u64 {
+    PRIMES[index] as u64
+}
+
+// Mirrors the digits of `a` in the base of the prime at `base_index` around the
+// radix point.
+pub fn radical_inverse(base_index: usize, mut a: u64) -> Float {
+    let base = prime(base_index);
+    let inv_base = (base as Float).recip();
+    let limit = u64::MAX / base - base;
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits = 0u64;
+    while a != 0 && reversed_digits < limit {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+
+    (reversed_digits as Float * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+pub fn inverse_radical_inverse(mut inverse: u64, base: u64, digits: u32) -> u64 {
+    let mut index = 0;
+    for _ in 0..digits {
+        let digit = inverse % base;
+        inverse /= base;
+        index = index * base + digit;
+    }
+    index
+}
+
+// Applies the same pseudo-random permutation, chosen by `hash`, to every digit.
+// The infinite run of leading zeros of `a` maps to a geometric tail.
+pub fn scrambled_radical_inverse(base_index: usize, mut a: u64, hash: u32) -> Float {
+    let base = prime(base_index);
+    let inv_base = (base as Float).recip();
+    let permute =
+        |digit: u64| permutation_element(digit as u32, base as u32, hash) as u64;
+    let limit = u64::MAX / base - base;
+    let mut inv_base_m = 1.0;
+    let mut reversed_digits = 0u64;
+    while a != 0 && reversed_digits < limit {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed_digits = reversed_digits * base + permute(digit);
+        inv_base_m *= inv_base;
+        a = next;
+    }
+
+    let tail = inv_base * permute(0) as Float / (1.0 - inv_base);
+    (inv_base_m * (reversed_digits as Float + tail)).min(ONE_MINUS_EPSILON)
+}
+
+// Owen scrambling: each digit is permuted depending on all the digits before it.
+pub fn owen_scrambled_radical_inverse(base_index: usize, mut a: u64, hash: u32) -> Float {
+    let base = prime(base_index);
+    let inv_base = (base as Float).recip();
+    let limit = u64::MAX / base - base;
+    let mut inv_base_m: Float = 1.0;
+    let mut reversed_digits = 0u64;
+    while 1.0 - (base - 1) as Float * inv_base_m < 1.0 && reversed_digits < limit {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_hash = mix_bits(hash as u64 ^ reversed_digits) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_hash) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+
+    (inv_base_m * reversed_digits as Float).min(ONE_MINUS_EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radical_inverse_mirrors_digits() {
+        assert_eq!(radical_inverse(0, 0), 0.0);
+        assert_eq!(radical_inverse(0, 1), 0.5);
+        assert_eq!(radical_inverse(0, 6), 0.375);
+        assert_eq!(radical_inverse(1, 1), 1.0 / 3.0);
+        assert!((radical_inverse(1, 4) - 4.0 / 9.0).abs() < 1E-15);
+
+        for a in 0..81 {
+            let inverse = (radical_inverse(1, a) * 81.0).round() as u64;
+            assert_eq!(inverse_radical_inverse(inverse, 3, 4), a);
+        }
+    }
+
+    #[test]
+    fn permutation_element_is_a_permutation() {
+        for n in [2, 3, 5, 7, 16, 97] {
+            for p in [0, 1, 0xdead_beef, 0xffff_ffff] {
+                let mut seen = vec![false; n as usize];
+                for i in 0..n {
+                    let j = permutation_element(i, n, p);
+                    assert!(!seen[j as usize]);
+                    seen[j as usize] = true;
+                }
+            }
+        }
+    }
+
+    fn first_points(
+        inverse: &impl Fn(usize, u64) -> Float,
+        base_index: usize,
+    ) -> Vec<Float> {
+        let count = prime(base_index).pow(if base_index == 0 { 6 } else { 3 });
+        (0..count).map(|a| inverse(base_index, a)).collect()
+    }
+
+    #[test]
+    fn permuted_digits_stay_evenly_spaced() {
+        // Every digit position goes through the same permutation, so the first b^k
+        // points stay a shifted copy of the b^-k lattice.
+        for hash in [1, 0x1234_5678, 0xffff_ffff] {
+            let inverse = |b, a| scrambled_radical_inverse(b, a, hash);
+            for base_index in [0, 1, 4] {
+                let mut points = first_points(&inverse, base_index);
+                points.sort_by(Float::total_cmp);
+                let count = points.len() as Float;
+                assert!(points.iter().all(|x| (0.0..1.0).contains(x)));
+                for pair in points.windows(2) {
+                    assert!(((pair[1] - pair[0]) * count - 1.0).abs() < 1E-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn owen_scrambling_keeps_strata() {
+        for hash in [1, 0x1234_5678, 0xffff_ffff] {
+            let inverse = |b, a| owen_scrambled_radical_inverse(b, a, hash);
+            for base_index in [0, 1, 4] {
+                let points = first_points(&inverse, base_index);
+                let mut strata = vec![false; points.len()];
+                for x in &points {
+                    let stratum = (x * points.len() as Float) as usize;
+                    assert!(!strata[stratum]);
+                    strata[stratum] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_bases_stay_in_range() {
+        for base_index in [30, 500, PRIME_TABLE_SIZE - 1] {
+            for a in [1, 12_345, u64::MAX / 3] {
+                let x = owen_scrambled_radical_inverse(base_index, a, 0x9e37_79b9);
+                assert!((0.0..1.0).contains(&x));
+                let x = scrambled_radical_inverse(base_index, a, 0x9e37_79b9);
+                assert!((0.0..1.0).contains(&x));
+            }
+        }
+    }
+
+    #[test]
+    fn scrambling_depends_on_hash() {
+        let a = scrambled_radical_inverse(2, 17, 1);
+        assert_ne!(a, scrambled_radical_inverse(2, 17, 2));
+        let a = owen_scrambled_radical_inverse(2, 17, 1);
+        assert_ne!(a, owen_scrambled_radical_inverse(2, 17, 2));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::*;
+use std::ops::{Add, Mul, Neg, Sub};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Quaternion {
+    pub v: Vector3,
+    pub w: Float,
+}
+
+impl Quaternion {
+    pub fn new(v: Vector3, w: Float) -> Quaternion {
+        Quaternion { v, w }
+    }
+
+    pub fn identity() -> Quaternion {
+        Quaternion::new(Vector3::zero(), 1.0)
+    }
+
+    // Reads the rotation from the upper 3x3 block, which has to be orthonormal
+    // (Shoemake).
+    pub fn from_matrix(m: &SquareMatrix<4>) -> Quaternion {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        if trace > 0.0 {
+            let s = (trace + 1.0).sqrt();
+            let w = s / 2.0;
+            let s = 0.5 / s;
+            return Quaternion::new(
+                Vector3::new([
+                    (m[2][1] - m[1][2]) * s,
+                    (m[0][2] - m[2][0]) * s,
+                    (m[1][0] - m[0][1]) * s,
+                ]),
+                w,
+            );
+        }
+
+        // Start from the largest diagonal element for stability.
+        let i = if m[1][1] > m[0][0] { 1 } else { 0 };
+        let i = if m[2][2] > m[i][i] { 2 } else { i };
+        let j = (i + 1) % 3;
+        let k = (j + 1) % 3;
+        let s = (m[i][i] - (m[j][j] + m[k][k]) + 1.0).sqrt();
+        let mut v = [0.0; 3];
+        v[i] = s * 0.5;
+        let s = if s != 0.0 { 0.5 / s } else { s };
+        v[j] = (m[j][i] + m[i][j]) * s;
+        v[k] = (m[k][i] + m[i][k]) * s;
+        Quaternion::new(Vector3::new(v), (m[k][j] - m[j][k]) * s)
+    }
+
+    pub fn to_matrix(&self) -> SquareMatrix<4> {
+        let (x, y, z, w) = (self.v[0], self.v[1], self.v[2], self.w);
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let (xy, xz, yz) = (x * y, x * z, y * z);
+        let (wx, wy, wz) = (x * w, y * w, z * w);
+        SquareMatrix::<4>::new([
+            [1.0 - 2.0 * (yy + zz), 2.0 * (xy - wz), 2.0 * (xz + wy), 0.0],
+            [2.0 * (xy + wz), 1.0 - 2.0 * (xx + zz), 2.0 * (yz - wx), 0.0],
+            [2.0 * (xz - wy), 2.0 * (yz + wx), 1.0 - 2.0 * (xx + yy), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn dot(&self, rhs: &Quaternion) -> Float {
+        self.v.dot(&rhs.v) + self.w * rhs.w
+    }
+
+    pub fn normalize(&self) -> Quaternion {
+        *self * self.dot(self).sqrt().recip()
+    }
+
+    // Falls back to a normalized lerp when the rotations are nearly parallel.
+    pub fn slerp(&self, rhs: &Quaternion, t: Float) -> Quaternion {
+        let cos_theta = self.dot(rhs);
+        if cos_theta > 0.9995 {
+            return (*self * (1.0 - t) + *rhs * t).normalize();
+        }
+
+        let theta = cos_theta.clamp(-1.0, 1.0).acos();
+        let perpendicular = (*rhs - *self * cos_theta).normalize();
+        *self * (theta * t).cos() + perpendicular * (theta * t).sin()
+    }
+}
+
+impl Add<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    fn add(self, rhs: Quaternion) -> Self::Output {
+        Quaternion::new(self.v + rhs.v, self.w + rhs.w)
+    }
+}
+
+impl Sub<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    fn sub(self, rhs: Quaternion) -> Self::Output {
+        Quaternion::new(self.v - rhs.v, self.w - rhs.w)
+    }
+}
+
+impl Mul<Float> for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, rhs: Float) -> Self::Output {
+        Quaternion::new(self.v * rhs, self.w * rhs)
+    }
+}
+
+impl Neg for Quaternion {
+    type Output = Quaternion;
+
+    fn neg(self) -> Self::Output {
+        Quaternion::new(-self.v, -self.w)
+    }
+}
@@ -1 +1,199 @@
+use crate::Float;
 
+pub const ONE_MINUS_EPSILON: Float = 1.0 - Float::EPSILON / 2.0;
+
+const MULTIPLIER: u64 = 0x5851_f42d_4c95_7f2d;
+const DEFAULT_STATE: u64 = 0x853c_49e6_748f_ea9b;
+const DEFAULT_STREAM: u64 = 0xda3e_39cb_94b9_5bdb;
+
+// PCG32 (XSH RR) by Melissa O'Neill. Each stream is an independent sequence with
+// a period of 2^64 and can be positioned anywhere with `advance`.
+#[derive(Copy, Clone, Debug)]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng::with_stream(seed, DEFAULT_STREAM >> 1)
+    }
+
+    pub fn with_stream(seed: u64, stream: u64) -> Rng {
+        let mut rng = Rng::default();
+        rng.set_sequence(seed, stream);
+        rng
+    }
+
+    pub fn set_sequence(&mut self, seed: u64, stream: u64) {
+        self.state = 0;
+        self.inc = (stream << 1) | 1;
+        self.uniform_u32();
+        self.state = self.state.wrapping_add(seed);
+        self.uniform_u32();
+    }
+
+    pub fn uniform_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+        let xor_shifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xor_shifted.rotate_right(rotation)
+    }
+
+    pub fn uniform_u64(&mut self) -> u64 {
+        let high = self.uniform_u32() as u64;
+        (high << 32) | self.uniform_u32() as u64
+    }
+
+    // Unbiased: values below `2^32 mod bound` are rejected.
+    pub fn uniform_below(&mut self, bound: u32) -> u32 {
+        assert!(bound > 0, "Empty range!");
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let r = self.uniform_u32();
+            if r >= threshold {
+                return r % bound;
+            }
+        }
+    }
+
+    pub fn uniform_range(&mut self, range: std::ops::Range<u32>) -> u32 {
+        range.start + self.uniform_below(range.end - range.start)
+    }
+
+    pub fn uniform_float(&mut self) -> Float {
+        (self.uniform_u32() as Float * 2.0_f64.powi(-32)).min(ONE_MINUS_EPSILON)
+    }
+
+    // Jumps `delta` steps forward (or backward when negative) in O(log delta).
+    pub fn advance(&mut self, delta: i64) {
+        let mut delta = delta as u64;
+        let (mut cur_mult, mut cur_plus) = (MULTIPLIER, self.inc);
+        let (mut acc_mult, mut acc_plus) = (1u64, 0u64);
+        while delta > 0 {
+            if delta & 1 == 1 {
+                acc_mult = acc_mult.wrapping_mul(cur_mult);
+                acc_plus = acc_plus.wrapping_mul(cur_mult).wrapping_add(cur_plus);
+            }
+            cur_plus = cur_mult.wrapping_add(1).wrapping_mul(cur_plus);
+            cur_mult = cur_mult.wrapping_mul(cur_mult);
+            delta >>= 1;
+        }
+        self.state = acc_mult.wrapping_mul(self.state).wrapping_add(acc_plus);
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng {
+            state: DEFAULT_STATE,
+            inc: DEFAULT_STREAM,
+        }
+    }
+}
+
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+// Returns the `i`-th element of a random permutation of `0..n` selected by `p`
+// (Kensler, "Correlated Multi-Jittered Sampling").
+pub fn permutation_element(mut i: u32, n: u32, p: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            return ((i as u64 + p as u64) % n as u64) as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_reference_sequence() {
+        // First outputs of the reference pcg32-demo seeded with (42, 54).
+        let mut rng = Rng::with_stream(42, 54);
+        let expected = [
+            0xa15c_02b7,
+            0x7b47_f409,
+            0xba1d_3330,
+            0x83d2_f293,
+            0xbfa4_784b,
+            0xcbed_606e,
+        ];
+        for e in expected {
+            assert_eq!(rng.uniform_u32(), e);
+        }
+    }
+
+    #[test]
+    fn advance_moves_both_ways() {
+        let mut rng = Rng::with_stream(7, 3);
+        let start = rng;
+        let values = (0..100).map(|_| rng.uniform_u32()).collect::<Vec<u32>>();
+
+        let mut jumped = start;
+        jumped.advance(37);
+        assert_eq!(jumped.uniform_u32(), values[37]);
+        jumped.advance(-38);
+        assert_eq!(jumped.uniform_u32(), values[0]);
+    }
+
+    #[test]
+    fn streams_differ() {
+        let mut a = Rng::with_stream(1, 1);
+        let mut b = Rng::with_stream(1, 2);
+        let same = (0..64)
+            .filter(|_| a.uniform_u32() == b.uniform_u32())
+            .count();
+        assert!(same < 2);
+    }
+
+    #[test]
+    fn bounded_values_are_uniform() {
+        let mut rng = Rng::new(5);
+        let mut counts = [0usize; 6];
+        for _ in 0..60_000 {
+            counts[rng.uniform_range(10..16) as usize - 10] += 1;
+        }
+        for c in counts {
+            assert!((9_500..10_500).contains(&c));
+        }
+
+        for _ in 0..10_000 {
+            let u = rng.uniform_float();
+            assert!((0.0..1.0).contains(&u));
+        }
+    }
+}
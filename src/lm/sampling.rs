@@ -0,0 +1,270 @@
+use crate::lm::rng::ONE_MINUS_EPSILON;
+use crate::*;
+use std::f64::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4, PI};
+
+pub fn concentric_disk(u: &Vector2) -> Vector2 {
+    let offset = Vector2::new([2.0 * u[0] - 1.0, 2.0 * u[1] - 1.0]);
+    if offset[0] == 0.0 && offset[1] == 0.0 {
+        return Vector2::zero();
+    }
+
+    let (r, theta) = if offset[0].abs() > offset[1].abs() {
+        (offset[0], FRAC_PI_4 * (offset[1] / offset[0]))
+    } else {
+        (offset[1], FRAC_PI_2 - FRAC_PI_4 * (offset[0] / offset[1]))
+    };
+    Vector2::new([r * theta.cos(), r * theta.sin()])
+}
+
+// Directions are in the local frame where the surface normal is +z.
+pub fn cosine_hemisphere(u: &Vector2) -> Vector3 {
+    let d = concentric_disk(u);
+    let z = (1.0 - d[0] * d[0] - d[1] * d[1]).max(0.0).sqrt();
+    Vector3::new([d[0], d[1], z])
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: Float) -> Float {
+    cos_theta.max(0.0) * FRAC_1_PI
+}
+
+// Uniform over the regular polygon inscribed in the unit circle with a vertex on
+// +x. The polygon is split into triangle fans around the centre and `u[0]` picks
+// the triangle.
+pub fn regular_polygon(u: &Vector2, sides: usize) -> Vector2 {
+    let sides = sides.max(3);
+    let scaled = u[0] * sides as Float;
+    let k = (scaled as usize).min(sides - 1);
+    let u_0 = scaled - k as Float;
+
+    let angle = 2.0 * PI / sides as Float;
+    let a = Vector2::new([(k as Float * angle).cos(), (k as Float * angle).sin()]);
+    let b = Vector2::new([
+        ((k + 1) as Float * angle).cos(),
+        ((k + 1) as Float * angle).sin(),
+    ]);
+    let s = u[1].sqrt();
+    (a * (1.0 - u_0) + b * u_0) * s
+}
+
+pub fn uniform_sphere(u: &Vector2) -> Vector3 {
+    let z = 1.0 - 2.0 * u[0];
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u[1];
+    Vector3::new([r * phi.cos(), r * phi.sin(), z])
+}
+
+pub fn uniform_sphere_pdf() -> Float {
+    0.25 * FRAC_1_PI
+}
+
+// Barycentric coordinates uniformly distributed over a triangle, using the
+// low-distortion mapping of Heitz.
+pub fn uniform_triangle(u: &Vector2) -> [Float; 3] {
+    let (b_0, b_1) = if u[0] < u[1] {
+        let b_0 = u[0] / 2.0;
+        (b_0, u[1] - b_0)
+    } else {
+        let b_1 = u[1] / 2.0;
+        (u[0] - b_1, b_1)
+    };
+    [b_0, b_1, 1.0 - b_0 - b_1]
+}
+
+// Weight of a sample drawn from `f` when `g` could have produced it too, with
+// `nf` and `ng` samples taken from each.
+pub fn power_heuristic(nf: usize, f_pdf: Float, ng: usize, g_pdf: Float) -> Float {
+    let f = nf as Float * f_pdf;
+    let g = ng as Float * g_pdf;
+    if f.is_infinite() {
+        return 1.0;
+    }
+    if f == 0.0 && g == 0.0 {
+        return 0.0;
+    }
+    (f * f) / (f * f + g * g)
+}
+
+// Piecewise constant distribution over [0, 1) with one segment per value of the
+// function, which is also usable to pick one of the values proportionally.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    func: Vec<Float>,
+    cdf: Vec<Float>,
+    integral: Float,
+}
+
+impl Distribution1D {
+    pub fn new(func: &[Float]) -> Distribution1D {
+        let func = func.iter().map(|f| f.abs()).collect::<Vec<Float>>();
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as Float;
+        }
+
+        // A function that is zero everywhere is sampled uniformly instead.
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+            *c = if integral == 0.0 {
+                i as Float / n as Float
+            } else {
+                *c / integral
+            };
+        }
+
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> Float {
+        self.integral
+    }
+
+    // The segment containing `u`, i.e. the last one whose cdf does not exceed it.
+    fn offset(&self, u: Float) -> usize {
+        let n = self.count();
+        self.cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(n.saturating_sub(1))
+    }
+
+    // Returns the sample in [0, 1), its density and the segment it falls in.
+    pub fn sample_continuous(&self, u: Float) -> (Float, Float, usize) {
+        let offset = self.offset(u);
+        let mut du = u - self.cdf[offset];
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        if width > 0.0 {
+            du /= width;
+        }
+        let pdf = self.density(offset);
+        let x = ((offset as Float + du) / self.count() as Float).min(ONE_MINUS_EPSILON);
+        (x, pdf, offset)
+    }
+
+    // Returns the chosen index, its probability and `u` remapped to [0, 1) within
+    // the segment so that it can be reused.
+    pub fn sample_discrete(&self, u: Float) -> (usize, Float, Float) {
+        let offset = self.offset(u);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let remapped = if width > 0.0 {
+            ((u - self.cdf[offset]) / width).min(ONE_MINUS_EPSILON)
+        } else {
+            0.0
+        };
+        (offset, self.discrete_pdf(offset), remapped)
+    }
+
+    // The density of `sample_continuous` within a segment.
+    fn density(&self, offset: usize) -> Float {
+        if self.integral > 0.0 {
+            self.func[offset] / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    pub fn discrete_pdf(&self, index: usize) -> Float {
+        if self.integral > 0.0 {
+            self.func[index] / (self.integral * self.count() as Float)
+        } else {
+            1.0 / self.count() as Float
+        }
+    }
+}
+
+// Piecewise constant distribution over [0, 1)^2 from `nu` x `nv` values stored
+// row by row. A row is picked from the marginal distribution of the row sums
+// first, then the column from that row.
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[Float], nu: usize, nv: usize) -> Distribution2D {
+        let conditional = func
+            .chunks_exact(nu)
+            .take(nv)
+            .map(Distribution1D::new)
+            .collect::<Vec<Distribution1D>>();
+        let marginal = Distribution1D::new(
+            &conditional
+                .iter()
+                .map(|d| d.integral())
+                .collect::<Vec<Float>>(),
+        );
+
+        Distribution2D {
+            conditional,
+            marginal,
+        }
+    }
+
+    pub fn sample_continuous(&self, u: &Vector2) -> (Vector2, Float) {
+        let (v, v_pdf, row) = self.marginal.sample_continuous(u[1]);
+        let (u, u_pdf, _) = self.conditional[row].sample_continuous(u[0]);
+        (Vector2::new([u, v]), u_pdf * v_pdf)
+    }
+
+    pub fn pdf(&self, p: &Vector2) -> Float {
+        let row = segment(p[1], self.marginal.count());
+        let conditional = &self.conditional[row];
+        let column = segment(p[0], conditional.count());
+        conditional.density(column) * self.marginal.density(row)
+    }
+}
+
+fn segment(x: Float, count: usize) -> usize {
+    ((x * count as Float).max(0.0) as usize).min(count - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lm::rng::Rng;
+
+    #[test]
+    fn distribution_2d_pdf_matches_samples() {
+        let (nu, nv) = (5, 3);
+        let func = [
+            1.0, 0.0, 2.0, 4.0, 1.0, //
+            0.5, 0.5, 0.0, 8.0, 3.0, //
+            0.0, 0.0, 0.0, 0.0, 6.0,
+        ];
+        let distribution = Distribution2D::new(&func, nu, nv);
+        let total = func.iter().sum::<Float>();
+        let mut counts = [0usize; 15];
+        let mut rng = Rng::new(41);
+        let count = 200_000;
+
+        for _ in 0..count {
+            let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+            let (p, pdf) = distribution.sample_continuous(&u);
+            assert!((distribution.pdf(&p) - pdf).abs() < 1E-12);
+            let cell = segment(p[0], nu) + nu * segment(p[1], nv);
+            // The density over the unit square is the value relative to the mean.
+            assert!((pdf - func[cell] * (nu * nv) as Float / total).abs() < 1E-12);
+            counts[cell] += 1;
+        }
+        for (f, n) in func.iter().zip(counts) {
+            assert!((n as Float / count as Float - f / total).abs() < 0.005);
+        }
+    }
+
+    #[test]
+    fn zero_distribution_2d_is_uniform() {
+        let distribution = Distribution2D::new(&[0.0; 6], 3, 2);
+        let (p, pdf) = distribution.sample_continuous(&Vector2::new([0.7, 0.2]));
+        assert_eq!(pdf, 1.0);
+        assert_eq!(distribution.pdf(&p), 1.0);
+    }
+}
@@ -1,5 +1,7 @@
+use crate::rt::ray::Differentials;
 use crate::*;
 
+#[derive(Copy, Clone, Debug)]
 pub struct Transform {
     matrix: SquareMatrix<4>,
     inversed: SquareMatrix<4>,
@@ -24,6 +26,80 @@ impl Transform {
         self.inversed
     }
 
+    pub fn identity() -> Transform {
+        Transform {
+            matrix: SquareMatrix::<4>::identity(),
+            inversed: SquareMatrix::<4>::identity(),
+        }
+    }
+
+    pub fn apply_point(&self, p: &Vector3) -> Vector3 {
+        point(&self.matrix, p)
+    }
+
+    // Bounds the error of `apply_point` on a point that already carries
+    // `p_error`, assuming an affine transform.
+    pub fn apply_point_error(&self, p: &Vector3, p_error: &Vector3) -> Vector3 {
+        point_error(&self.matrix, p, p_error)
+    }
+
+    pub fn apply_vector(&self, v: &Vector3) -> Vector3 {
+        vector(&self.matrix, v)
+    }
+
+    pub fn apply_normal(&self, n: &Vector3) -> Vector3 {
+        normal(&self.inversed, n)
+    }
+
+    pub fn apply_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(self.apply_point(&ray.o), self.apply_vector(&ray.d))
+            .with_time(ray.time)
+            .with_differentials(ray.differentials.map(|d| Differentials {
+                rx_o: self.apply_point(&d.rx_o),
+                rx_d: self.apply_vector(&d.rx_d),
+                ry_o: self.apply_point(&d.ry_o),
+                ry_d: self.apply_vector(&d.ry_d),
+            }))
+    }
+
+    pub fn apply_bound(&self, b: &Bound<3>) -> Bound<3> {
+        bound(&self.matrix, b)
+    }
+
+    pub fn apply_inverse_point(&self, p: &Vector3) -> Vector3 {
+        point(&self.inversed, p)
+    }
+
+    pub fn apply_inverse_point_error(&self, p: &Vector3, p_error: &Vector3) -> Vector3 {
+        point_error(&self.inversed, p, p_error)
+    }
+
+    pub fn apply_inverse_vector(&self, v: &Vector3) -> Vector3 {
+        vector(&self.inversed, v)
+    }
+
+    pub fn apply_inverse_normal(&self, n: &Vector3) -> Vector3 {
+        normal(&self.matrix, n)
+    }
+
+    pub fn apply_inverse_ray(&self, ray: &Ray) -> Ray {
+        Ray::new(
+            self.apply_inverse_point(&ray.o),
+            self.apply_inverse_vector(&ray.d),
+        )
+        .with_time(ray.time)
+        .with_differentials(ray.differentials.map(|d| Differentials {
+            rx_o: self.apply_inverse_point(&d.rx_o),
+            rx_d: self.apply_inverse_vector(&d.rx_d),
+            ry_o: self.apply_inverse_point(&d.ry_o),
+            ry_d: self.apply_inverse_vector(&d.ry_d),
+        }))
+    }
+
+    pub fn apply_inverse_bound(&self, b: &Bound<3>) -> Bound<3> {
+        bound(&self.inversed, b)
+    }
+
     pub const fn transpose(&self) -> Transform {
         Transform {
             matrix: self.inversed,
@@ -40,9 +116,9 @@ impl Transform {
         ]);
 
         let inversed = SquareMatrix::<4>::new([
-            [1.0, 0.0, 0.0, delta[0]],
-            [0.0, 1.0, 0.0, delta[1]],
-            [0.0, 0.0, 1.0, delta[2]],
+            [1.0, 0.0, 0.0, -delta[0]],
+            [0.0, 1.0, 0.0, -delta[1]],
+            [0.0, 0.0, 1.0, -delta[2]],
             [0.0, 0.0, 0.0, 1.0],
         ]);
 
@@ -116,13 +192,25 @@ impl Transform {
     }
 
     pub fn look_at(position: &Vector<3>, look: &Vector<3>, up: &Vector<3>) -> Transform {
-        let direction = (look - position).normalize();
-
-        if up.normalize().cross(&direction).magnitude() == 0.0 {
-            panic!("look at!")
-        }
+        let direction = look - position;
+        let direction = if direction.magnitude() == 0.0 {
+            Vector::<3>::new([0.0, 0.0, 1.0])
+        } else {
+            direction.normalize()
+        };
 
-        let right = up.normalize().cross(&direction).normalize();
+        // An up vector that is missing or parallel to the view direction leaves the
+        // roll undefined, any vector perpendicular to the direction will do then.
+        let right = up.cross(&direction);
+        let right = if right.magnitude() <= 1E-6 * up.magnitude() {
+            direction
+                .coordinate_system()
+                .1
+                .cross(&direction)
+                .normalize()
+        } else {
+            right.normalize()
+        };
         let new_up = &direction.cross(&right);
 
         let to_world = SquareMatrix::<4>::new([
@@ -141,8 +229,8 @@ impl Transform {
     }
 
     pub fn orthographic(z_near: Float, z_far: Float) -> Transform {
-        &Self::scale(1.0, 1.0, (z_far - z_near).recip())
-            * &Self::translate(&Vector::<3>::new([0.0, 0.0, -z_near]))
+        Self::scale(1.0, 1.0, (z_far - z_near).recip())
+            * Self::translate(&Vector::<3>::new([0.0, 0.0, -z_near]))
     }
 
     pub fn perspective(fov_degrees: Float, z_near: Float, z_far: Float) -> Transform {
@@ -159,8 +247,64 @@ impl Transform {
         ]);
 
         let tan_recip = Float::tan(fov_degrees.to_radians() * 0.5).recip();
-        &Self::scale(tan_recip, tan_recip, 1.0) * &Self::build(&perspective)
+        Self::scale(tan_recip, tan_recip, 1.0) * Self::build(&perspective)
+    }
+}
+
+fn point(m: &SquareMatrix<4>, p: &Vector3) -> Vector3 {
+    let h = m * &Vector4::new([p[0], p[1], p[2], 1.0]);
+    let v = Vector3::new([h[0], h[1], h[2]]);
+    if h[3] == 1.0 {
+        v
+    } else {
+        v / h[3]
+    }
+}
+
+fn point_error(m: &SquareMatrix<4>, p: &Vector3, p_error: &Vector3) -> Vector3 {
+    let mut e = [0.0; 3];
+    for (i, e) in e.iter_mut().enumerate() {
+        let carried = (0..3).map(|j| m[i][j].abs() * p_error[j]).sum::<Float>();
+        let rounding = (0..3).map(|j| (m[i][j] * p[j]).abs()).sum::<Float>();
+        *e = (gamma(3) + 1.0) * carried + gamma(3) * (rounding + m[i][3].abs());
     }
+    Vector3::new(e)
+}
+
+fn vector(m: &SquareMatrix<4>, v: &Vector3) -> Vector3 {
+    let h = m * &Vector4::new([v[0], v[1], v[2], 0.0]);
+    Vector3::new([h[0], h[1], h[2]])
+}
+
+// Normals are transformed by the inverse transpose, so `inversed` is the inverse
+// of the transform being applied and is read column-wise.
+fn normal(inversed: &SquareMatrix<4>, n: &Vector3) -> Vector3 {
+    let mut v = [0.0; 3];
+    for (i, c) in v.iter_mut().enumerate() {
+        *c = inversed[0][i] * n[0] + inversed[1][i] * n[1] + inversed[2][i] * n[2];
+    }
+    Vector3::new(v)
+}
+
+fn bound(m: &SquareMatrix<4>, b: &Bound<3>) -> Bound<3> {
+    if (0..3).any(|i| b[i].0 > b[i].1) {
+        return *b;
+    }
+    if !b.is_finite() {
+        return Bound::<3>::all();
+    }
+
+    let mut bound = Bound::<3>::empty();
+    for corner in 0..8 {
+        let p = Vector3::new([
+            if corner & 1 == 0 { b[0].0 } else { b[0].1 },
+            if corner & 2 == 0 { b[1].0 } else { b[1].1 },
+            if corner & 4 == 0 { b[2].0 } else { b[2].1 },
+        ]);
+        bound = bound.union_point(&point(m, &p));
+    }
+
+    bound
 }
 
 impl std::ops::Mul<&Transform> for &Transform {
@@ -177,10 +321,226 @@ impl std::ops::Mul<&Transform> for &Transform {
 impl std::ops::Mul<Transform> for Transform {
     type Output = Transform;
 
-    fn mul(self, rhs: &Transform) -> Self::Output {
+    fn mul(self, rhs: Transform) -> Self::Output {
         Transform {
             matrix: self.matrix * rhs.matrix,
             inversed: rhs.inversed * self.inversed,
         }
     }
 }
+
+// Interpolates between two keyframes over [start_time, end_time]. The keyframes
+// are decomposed into translation, rotation and scale, M = T R S, so that the
+// rotation can be interpolated on the quaternion arc instead of element-wise.
+#[derive(Copy, Clone, Debug)]
+pub struct AnimatedTransform {
+    start: Transform,
+    end: Transform,
+    start_time: Float,
+    end_time: Float,
+    animated: bool,
+    translations: [Vector3; 2],
+    rotations: [Quaternion; 2],
+    scales: [SquareMatrix<4>; 2],
+}
+
+impl AnimatedTransform {
+    pub fn new(
+        start: Transform,
+        start_time: Float,
+        end: Transform,
+        end_time: Float,
+    ) -> AnimatedTransform {
+        let animated = start.matrix.m != end.matrix.m;
+        let (t_0, r_0, s_0) = decompose(&start.matrix);
+        let (t_1, mut r_1, s_1) = decompose(&end.matrix);
+        // Take the shorter way around.
+        if r_0.dot(&r_1) < 0.0 {
+            r_1 = -r_1;
+        }
+
+        AnimatedTransform {
+            start,
+            end,
+            start_time,
+            end_time,
+            animated,
+            translations: [t_0, t_1],
+            rotations: [r_0, r_1],
+            scales: [s_0, s_1],
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.animated
+    }
+
+    pub fn interpolate(&self, time: Float) -> Transform {
+        if !self.animated || time <= self.start_time {
+            return self.start;
+        }
+        if time >= self.end_time {
+            return self.end;
+        }
+
+        let dt = (time - self.start_time) / (self.end_time - self.start_time);
+        let translation = self.translations[0] * (1.0 - dt) + self.translations[1] * dt;
+        let rotation = self.rotations[0].slerp(&self.rotations[1], dt);
+        let mut scale = SquareMatrix::<4>::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                scale.m[i][j] =
+                    self.scales[0][i][j] * (1.0 - dt) + self.scales[1][i][j] * dt;
+            }
+        }
+
+        let rotation = rotation.to_matrix();
+        Transform::translate(&translation)
+            * Transform {
+                matrix: rotation,
+                inversed: rotation.transpose(),
+            }
+            * Transform::build(&scale)
+    }
+
+    pub fn apply_ray(&self, ray: &Ray) -> Ray {
+        self.interpolate(ray.time).apply_ray(ray)
+    }
+
+    pub fn apply_inverse_ray(&self, ray: &Ray) -> Ray {
+        self.interpolate(ray.time).apply_inverse_ray(ray)
+    }
+
+    // Unions the bounds at evenly spaced times and pads them by the chord error.
+    // A point moves along T(t) + R(t) S(t) p with linear T and S and a rotation
+    // at constant speed, so between two samples h apart it strays at most
+    // h^2 / 8 max |p''| from the chord joining them, which the union covers.
+    pub fn motion_bound(&self, b: &Bound<3>) -> Bound<3> {
+        if !self.animated {
+            return self.start.apply_bound(b);
+        }
+
+        const STEPS: usize = 64;
+        let bound = (0..=STEPS).fold(Bound::<3>::empty(), |bound, i| {
+            let time = self.start_time
+                + (self.end_time - self.start_time) * i as Float / STEPS as Float;
+            bound.union(&self.interpolate(time).apply_bound(b))
+        });
+        if b.is_empty() || !b.is_finite() {
+            return bound;
+        }
+
+        // Angle swept by the rotation, with some slack for the normalized lerp
+        // used on nearly parallel keyframes, whose speed is not quite constant.
+        let cos_half = self.rotations[0].dot(&self.rotations[1]).clamp(-1.0, 1.0);
+        let theta = 1.05 * 2.0 * cos_half.acos();
+        let mut acceleration: Float = 0.0;
+        for corner in 0..8 {
+            let p = Vector3::new([0, 1, 2].map(|i| match corner >> i & 1 {
+                0 => b[i].0,
+                _ => b[i].1,
+            }));
+            let scaled = vector(&self.scales[0], &p)
+                .magnitude()
+                .max(vector(&self.scales[1], &p).magnitude());
+            let stretch =
+                (vector(&self.scales[1], &p) - vector(&self.scales[0], &p)).magnitude();
+            acceleration =
+                acceleration.max(theta * theta * scaled + 2.0 * theta * stretch);
+        }
+
+        let pad = acceleration / (8.0 * (STEPS * STEPS) as Float);
+        Bound::<3>::new(bound.b.map(|(min, max)| (min - pad, max + pad)))
+    }
+}
+
+impl From<Transform> for AnimatedTransform {
+    fn from(transform: Transform) -> AnimatedTransform {
+        AnimatedTransform::new(transform, 0.0, transform, 1.0)
+    }
+}
+
+// Polar decomposition of the upper 3x3 block by averaging the matrix with its
+// inverse transpose until it converges to the rotation.
+fn decompose(m: &SquareMatrix<4>) -> (Vector3, Quaternion, SquareMatrix<4>) {
+    let translation = Vector3::new([m[0][3], m[1][3], m[2][3]]);
+    let mut linear = *m;
+    for i in 0..3 {
+        linear.m[i][3] = 0.0;
+        linear.m[3][i] = 0.0;
+    }
+    linear.m[3][3] = 1.0;
+
+    let mut rotation = linear;
+    for _ in 0..100 {
+        let inverse_transpose = match rotation.inverse() {
+            Some(inverse) => inverse.transpose(),
+            None => break,
+        };
+        let mut norm: Float = 0.0;
+        let mut next = rotation;
+        for i in 0..3 {
+            let mut row = 0.0;
+            for j in 0..3 {
+                next.m[i][j] = 0.5 * (rotation[i][j] + inverse_transpose[i][j]);
+                row += (rotation[i][j] - next[i][j]).abs();
+            }
+            norm = norm.max(row);
+        }
+        rotation = next;
+        if norm <= 1E-4 {
+            break;
+        }
+    }
+
+    let scale = match rotation.inverse() {
+        Some(inverse) => inverse * linear,
+        None => SquareMatrix::<4>::identity(),
+    };
+    (translation, Quaternion::from_matrix(&rotation), scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn motion_bound_contains_every_time() {
+        let b = Bound::<3>::new([(4.0, 5.0), (-0.5, 0.5), (-1.0, 2.0)]);
+        let cases = [
+            (
+                Transform::identity(),
+                Transform::rotate_y(170.0) * Transform::scale(2.0, 1.0, 0.5),
+            ),
+            (
+                Transform::rotate_z(-30.0),
+                Transform::translate(&Vector3::new([1.0, 2.0, 3.0]))
+                    * Transform::rotate_x(90.0),
+            ),
+            (Transform::rotate_x(1.0), Transform::rotate_x(2.5)),
+        ];
+
+        for (start, end) in cases {
+            let animated = AnimatedTransform::new(start, 0.0, end, 1.0);
+            let bound = animated.motion_bound(&b);
+            for i in 0..=10_000 {
+                let transform = animated.interpolate(i as Float / 10_000.0);
+                for corner in 0..8 {
+                    let p = Vector3::new([0, 1, 2].map(|k| match corner >> k & 1 {
+                        0 => b[k].0,
+                        _ => b[k].1,
+                    }));
+                    assert!(bound.contains(&transform.apply_point(&p)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn translate_inverts() {
+        let t = Transform::translate(&Vector3::new([1.0, -2.0, 3.0]));
+        let p = Vector3::new([0.5, 0.25, -4.0]);
+        assert_eq!(t.apply_inverse_point(&t.apply_point(&p)).v, p.v);
+        assert_eq!(t.apply_point(&p).v, [1.5, -1.75, -1.0]);
+    }
+}
@@ -1,29 +1,49 @@
-use aisth::rt::{camera::Camera, film::Film};
+use aisth::rt::{
+    camera::PerspectiveCamera,
+    film::Film,
+    filter::{Filter, FilterKind},
+    integrator::{Integrator, PathIntegrator},
+    material::Lambertian,
+    sampler::{HaltonSampler, Randomization},
+};
 use aisth::*;
+use std::sync::Arc;
 
 fn main() {
-    let mut film = Film::new(80, 45);
-    let camera = Camera::build(
+    let mut film = Film::new(80, 45).with_filter(Filter::new(
+        FilterKind::Mitchell {
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        },
+        Vector2::new([2.0, 2.0]),
+    ));
+    let camera = PerspectiveCamera::build(
         film.clone(),
         Vector3::new([-3.0, 1.0, 0.0]),
         Vector3::new([0.0, 1.0, 0.0]),
+        Vector3::new([0.0, 1.0, 0.0]),
         60.0,
     );
 
-    let p = Plane::new(Vector3::new([0.0, 0.0, 0.0]), Vector3::new([0.0, 1.0, 0.0]));
+    let ground = Arc::new(Lambertian::new(Vector3::new([0.5, 0.7, 0.3])));
+    let white = Arc::new(Lambertian::new(Vector3::new([0.8, 0.8, 0.8])));
+    let scene = Scene::new()
+        .push_transformed(
+            Primitive::new(
+                Plane::new(Vector3::new([0.0, 0.0, 0.0]), Vector3::new([0.0, 1.0, 0.0])),
+                ground,
+            ),
+            Transform::identity(),
+        )
+        .push_transformed(
+            Primitive::new(Sphere::new(Vector3::new([0.0, 1.0, 0.0]), 1.0), white),
+            Transform::identity(),
+        );
 
-    for i in 0..film.size().0 {
-        for j in 0..film.size().1 {
-            if let Some(_) = p.intersect(
-                &camera.cast_ray(i, j),
-                &Interval::new([(1E-5, Float::INFINITY)]),
-            ) {
-                film.write(i, j, Vector::<3>::new([0.5, 0.7, 0.3]));
-            } else {
-                film.write(i, j, camera.cast_ray(i, j).d)
-            }
-        }
-    }
+    let sampler = HaltonSampler::new(16, film.size(), Randomization::Owen, 0);
+    let integrator =
+        PathIntegrator::new(8).with_background(Vector3::new([0.6, 0.8, 1.0]));
+    integrator.render(&camera, &scene, &mut film, &sampler);
 
     film.save("test.ppm").expect("Cannot create file.");
 }
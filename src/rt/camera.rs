@@ -1,55 +1,530 @@
 use crate::{
-    rt::{film::Film, ray::Ray},
+    lm::sampling::{concentric_disk, regular_polygon},
+    rt::{
+        film::Film,
+        ray::{Differentials, Ray},
+    },
     *,
 };
+use std::f64::consts::PI;
 
-pub struct Camera {
+// `film` is a continuous raster position with pixel centres at half-integers,
+// `lens` and `time` are uniform over [0, 1).
+#[derive(Copy, Clone, Debug)]
+pub struct CameraSample {
+    pub film: Vector2,
+    pub lens: Vector2,
+    pub time: Float,
+}
+
+impl CameraSample {
+    pub fn new(film: Vector2, lens: Vector2, time: Float) -> CameraSample {
+        CameraSample { film, lens, time }
+    }
+}
+
+// Camera space is left-handed, looking down +z with +y up.
+pub trait Camera: Send + Sync {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray>;
+
+    fn base(&self) -> &CameraBase;
+
+    fn base_mut(&mut self) -> &mut CameraBase;
+
+    fn film(&self) -> &Film {
+        &self.base().film
+    }
+
+    // Offset rays reuse the lens and time of the sample so that only the film
+    // position differs.
+    fn generate_ray_differential(&self, sample: &CameraSample) -> Option<Ray> {
+        let ray = self.generate_ray(sample)?;
+        let offset = |dx: Float, dy: Float| {
+            let film = sample.film + Vector2::new([dx, dy]);
+            self.generate_ray(&CameraSample::new(film, sample.lens, sample.time))
+        };
+        let differentials = match (offset(1.0, 0.0), offset(0.0, 1.0)) {
+            (Some(rx), Some(ry)) => Some(Differentials {
+                rx_o: rx.o,
+                rx_d: rx.d,
+                ry_o: ry.o,
+                ry_d: ry.d,
+            }),
+            _ => None,
+        };
+        Some(ray.with_differentials(differentials))
+    }
+
+    fn cast_ray(&self, x: usize, y: usize) -> Option<Ray> {
+        self.generate_ray(&CameraSample::new(
+            Vector2::new([x as Float + 0.5, y as Float + 0.5]),
+            Vector2::new([0.5, 0.5]),
+            0.0,
+        ))
+    }
+
+    // Rotates the camera around its view direction, counterclockwise as seen
+    // from the camera.
+    fn with_roll(mut self, degrees: Float) -> Self
+    where
+        Self: Sized,
+    {
+        let base = self.base_mut();
+        base.roll = base.roll * Transform::rotate_z(degrees);
+        base.update();
+        self
+    }
+
+    fn with_shutter(mut self, open: Float, close: Float) -> Self
+    where
+        Self: Sized,
+    {
+        let base = self.base_mut();
+        base.shutter = (open, close);
+        base.update();
+        self
+    }
+
+    // The camera moves from its transform at shutter open to `to_world_space` at
+    // shutter close. Any roll applies to both.
+    fn with_motion(mut self, to_world_space: Transform) -> Self
+    where
+        Self: Sized,
+    {
+        let base = self.base_mut();
+        base.end = to_world_space;
+        base.update();
+        self
+    }
+}
+
+// Film, placement and shutter shared by every projection. The roll is kept apart
+// from the keyframes so that it is applied to both whichever is set first.
+pub struct CameraBase {
     film: Film,
-    position: Vector<3>,
+    start: Transform,
+    end: Transform,
+    roll: Transform,
+    shutter: (Float, Float),
+    to_world_space: AnimatedTransform,
+}
+
+impl CameraBase {
+    // `to_world_space` maps camera space to world space.
+    pub fn new(film: Film, to_world_space: Transform) -> CameraBase {
+        CameraBase {
+            film,
+            start: to_world_space,
+            end: to_world_space,
+            roll: Transform::identity(),
+            shutter: (0.0, 1.0),
+            to_world_space: to_world_space.into(),
+        }
+    }
+
+    pub fn film(&self) -> &Film {
+        &self.film
+    }
+
+    pub fn shutter_time(&self, u: Float) -> Float {
+        self.shutter.0 + (self.shutter.1 - self.shutter.0) * u
+    }
+
+    // Moves a camera space ray to world space at the ray's time.
+    pub fn to_world_ray(&self, ray: &Ray) -> Ray {
+        let ray = self.to_world_space.apply_ray(ray);
+        Ray::new(ray.o, ray.d.normalize()).with_time(ray.time)
+    }
+
+    fn update(&mut self) {
+        self.to_world_space = AnimatedTransform::new(
+            self.start * self.roll,
+            self.shutter.0,
+            self.end * self.roll,
+            self.shutter.1,
+        );
+    }
+}
+
+// Which film axis the field of view spans.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FovAxis {
+    Horizontal,
+    Vertical,
+}
+
+pub struct PerspectiveCamera {
+    base: CameraBase,
+    fov_axis: FovAxis,
+    lens_shift: Vector<2>,
     from_clip_space: Transform,
-    to_world_space: Transform,
+    lens_radius: Float,
+    focus_distance: Float,
+    blades: usize,
 }
 
-impl Camera {
+impl PerspectiveCamera {
     pub fn build(
         film: Film,
         position: Vector<3>,
-        look: Vector<3>,
+        target: Vector<3>,
+        up: Vector<3>,
         fov_degrees: Float,
-    ) -> Camera {
-        let from_clip_space = Transform::perspective(fov_degrees, 1.0, 2.0).transpose();
-        let to_world_space =
-            Transform::look_at(&position, &look, &Vector::<3>::new([0.0, 1.0, 0.0]))
-                .transpose();
+    ) -> PerspectiveCamera {
+        Self::from_transform(film, look_at(&position, &target, &up), fov_degrees)
+    }
 
-        Camera {
-            film,
-            position,
-            from_clip_space,
-            to_world_space,
-        }
-    }
-
-    pub fn cast_ray(&self, x: usize, y: usize) -> Ray {
-        let offset = Vector::<2>::new([0.5, 0.5]);
-        let mut ndc = (Vector::<2>::new([x as Float, y as Float]) + offset)
-            / Vector::<2>::new([
-                self.film.size().0 as Float,
-                self.film.size().1 as Float,
-            ]);
-        ndc[1] = 1.0 - ndc[1];
-        ndc = ndc * 2.0 - Vector::<2>::new([1.0, 1.0]);
-        ndc[0] *= self.film.aspect();
-        let clip = Vector::<4>::new([ndc[0], ndc[1], 0.0, 1.0]);
-        let world = self.to_world_space.matrix() * self.from_clip_space.matrix() * clip;
-        Ray::new(
-            self.position,
-            (Vector::<3>::new([world[0], world[1], world[2]]) - self.position)
-                .normalize(),
+    // `to_world_space` maps camera space to world space.
+    pub fn from_transform(
+        film: Film,
+        to_world_space: Transform,
+        fov_degrees: Float,
+    ) -> PerspectiveCamera {
+        PerspectiveCamera {
+            base: CameraBase::new(film, to_world_space),
+            fov_axis: FovAxis::Vertical,
+            lens_shift: Vector::<2>::zero(),
+            from_clip_space: Transform::perspective(fov_degrees, 1.0, 2.0).transpose(),
+            lens_radius: 0.0,
+            focus_distance: 1.0,
+            blades: 0,
+        }
+    }
+
+    pub fn with_fov_axis(mut self, fov_axis: FovAxis) -> PerspectiveCamera {
+        self.fov_axis = fov_axis;
+        self
+    }
+
+    // Offsets the image plane window without tilting the view, in units of half
+    // the film extent along the field of view axis.
+    pub fn with_lens_shift(mut self, lens_shift: Vector<2>) -> PerspectiveCamera {
+        self.lens_shift = lens_shift;
+        self
+    }
+
+    // A zero lens radius keeps the pinhole model.
+    pub fn with_thin_lens(
+        mut self,
+        lens_radius: Float,
+        focus_distance: Float,
+    ) -> PerspectiveCamera {
+        self.lens_radius = lens_radius.max(0.0);
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    // With fewer than three blades the aperture is a disk.
+    pub fn with_blades(mut self, blades: usize) -> PerspectiveCamera {
+        self.blades = blades;
+        self
+    }
+
+    fn ray(&self, film_point: &Vector<2>, lens_point: &Vector<2>, time: Float) -> Ray {
+        let mut ndc = film_to_ndc(&self.base.film, film_point);
+        match self.fov_axis {
+            FovAxis::Horizontal => ndc[1] /= self.base.film.aspect(),
+            FovAxis::Vertical => ndc[0] *= self.base.film.aspect(),
+        }
+        ndc = ndc + self.lens_shift;
+        let clip = Vector::<3>::new([ndc[0], ndc[1], 0.0]);
+        let direction = self.from_clip_space.apply_point(&clip).normalize();
+        if self.lens_radius == 0.0 {
+            return self
+                .base
+                .to_world_ray(&Ray::new(Vector::<3>::zero(), direction).with_time(time));
+        }
+
+        // Every ray through the lens converges on the point of the plane of focus
+        // that the pinhole ray would hit.
+        let focus = direction * (self.focus_distance / direction[2]);
+        let origin = Vector::<3>::new([lens_point[0], lens_point[1], 0.0]);
+        self.base
+            .to_world_ray(&Ray::new(origin, focus - origin).with_time(time))
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let lens_point = if self.blades >= 3 {
+            regular_polygon(&sample.lens, self.blades)
+        } else {
+            concentric_disk(&sample.lens)
+        };
+        Some(self.ray(
+            &sample.film,
+            &(lens_point * self.lens_radius),
+            self.base.shutter_time(sample.time),
+        ))
+    }
+
+    fn base(&self) -> &CameraBase {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut CameraBase {
+        &mut self.base
+    }
+
+    fn cast_ray(&self, x: usize, y: usize) -> Option<Ray> {
+        Some(self.ray(
+            &Vector::<2>::new([x as Float + 0.5, y as Float + 0.5]),
+            &Vector::<2>::zero(),
+            self.base.shutter_time(0.0),
+        ))
+    }
+}
+
+// Parallel rays leaving the camera's xy plane. The screen window is the region
+// of that plane, in camera space units, that the film covers.
+pub struct OrthographicCamera {
+    base: CameraBase,
+    from_raster: Transform,
+}
+
+impl OrthographicCamera {
+    pub fn build(
+        film: Film,
+        position: Vector<3>,
+        target: Vector<3>,
+        up: Vector<3>,
+        screen_window: Bound<2>,
+    ) -> OrthographicCamera {
+        Self::from_transform(film, look_at(&position, &target, &up), screen_window)
+    }
+
+    pub fn from_transform(
+        film: Film,
+        to_world_space: Transform,
+        screen_window: Bound<2>,
+    ) -> OrthographicCamera {
+        // Raster space starts at the top left corner of the film with y pointing
+        // down, screen space is the projected xy plane the window is given in.
+        let (width, height) = film.size();
+        let window = &screen_window;
+        let to_screen =
+            Transform::translate(&Vector::<3>::new([window[0].0, window[1].1, 0.0]))
+                * Transform::scale(
+                    (window[0].1 - window[0].0) / width as Float,
+                    (window[1].0 - window[1].1) / height as Float,
+                    1.0,
+                );
+        let from_raster = Transform::orthographic(0.0, 1.0).transpose() * to_screen;
+
+        OrthographicCamera {
+            base: CameraBase::new(film, to_world_space),
+            from_raster,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let raster = Vector::<3>::new([sample.film[0], sample.film[1], 0.0]);
+        let origin = self.from_raster.apply_point(&raster);
+        let ray = Ray::new(origin, Vector::<3>::new([0.0, 0.0, 1.0]));
+        Some(
+            self.base
+                .to_world_ray(&ray.with_time(self.base.shutter_time(sample.time))),
         )
     }
 
-    pub const fn film(&self) -> &Film {
-        &self.film
+    fn base(&self) -> &CameraBase {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut CameraBase {
+        &mut self.base
+    }
+}
+
+// Equidistant fisheye, the angle from the view axis grows linearly with the
+// distance from the image centre. The image circle spans the shorter side of
+// the film and points outside of it have no ray.
+pub struct FisheyeCamera {
+    base: CameraBase,
+    fov: Float,
+}
+
+impl FisheyeCamera {
+    pub fn build(
+        film: Film,
+        position: Vector<3>,
+        target: Vector<3>,
+        up: Vector<3>,
+        fov_degrees: Float,
+    ) -> FisheyeCamera {
+        Self::from_transform(film, look_at(&position, &target, &up), fov_degrees)
+    }
+
+    pub fn from_transform(
+        film: Film,
+        to_world_space: Transform,
+        fov_degrees: Float,
+    ) -> FisheyeCamera {
+        FisheyeCamera {
+            base: CameraBase::new(film, to_world_space),
+            fov: fov_degrees.to_radians(),
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let mut ndc = film_to_ndc(&self.base.film, &sample.film);
+        let aspect = self.base.film.aspect();
+        if aspect > 1.0 {
+            ndc[0] *= aspect;
+        } else {
+            ndc[1] /= aspect;
+        }
+
+        let r = ndc.magnitude();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = r * self.fov * 0.5;
+        let phi = ndc[1].atan2(ndc[0]);
+        let direction = Vector::<3>::new([
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        ]);
+        let ray = Ray::new(Vector::<3>::zero(), direction);
+        Some(
+            self.base
+                .to_world_ray(&ray.with_time(self.base.shutter_time(sample.time))),
+        )
+    }
+
+    fn base(&self) -> &CameraBase {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut CameraBase {
+        &mut self.base
+    }
+}
+
+// Latitude-longitude projection of the full sphere of directions. The film
+// centre looks down the view axis, x spans the longitude and y goes from the up
+// direction at the top to the down direction at the bottom.
+pub struct EquirectangularCamera {
+    base: CameraBase,
+}
+
+impl EquirectangularCamera {
+    pub fn build(
+        film: Film,
+        position: Vector<3>,
+        target: Vector<3>,
+        up: Vector<3>,
+    ) -> EquirectangularCamera {
+        Self::from_transform(film, look_at(&position, &target, &up))
+    }
+
+    pub fn from_transform(
+        film: Film,
+        to_world_space: Transform,
+    ) -> EquirectangularCamera {
+        EquirectangularCamera {
+            base: CameraBase::new(film, to_world_space),
+        }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn generate_ray(&self, sample: &CameraSample) -> Option<Ray> {
+        let (width, height) = self.base.film.size();
+        let phi = 2.0 * PI * (sample.film[0] / width as Float - 0.5);
+        let theta = PI * sample.film[1] / height as Float;
+        let direction = Vector::<3>::new([
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        ]);
+        let ray = Ray::new(Vector::<3>::zero(), direction);
+        Some(
+            self.base
+                .to_world_ray(&ray.with_time(self.base.shutter_time(sample.time))),
+        )
+    }
+
+    fn base(&self) -> &CameraBase {
+        &self.base
+    }
+
+    fn base_mut(&mut self) -> &mut CameraBase {
+        &mut self.base
+    }
+}
+
+fn look_at(position: &Vector<3>, target: &Vector<3>, up: &Vector<3>) -> Transform {
+    Transform::look_at(position, target, up).transpose()
+}
+
+// Maps the film onto [-1, 1]^2 with +y at the top.
+fn film_to_ndc(film: &Film, film_point: &Vector<2>) -> Vector<2> {
+    let (width, height) = film.size();
+    let mut ndc = film_point / &Vector::<2>::new([width as Float, height as Float]);
+    ndc[1] = 1.0 - ndc[1];
+    ndc * 2.0 - Vector::<2>::new([1.0, 1.0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin(camera: &OrthographicCamera, x: Float, y: Float) -> Vector<3> {
+        let film = Vector2::new([x, y]);
+        let sample = CameraSample::new(film, Vector2::zero(), 0.0);
+        let ray = camera.generate_ray(&sample).unwrap();
+        assert_eq!(ray.d.v, [0.0, 0.0, 1.0]);
+        ray.o
+    }
+
+    #[test]
+    fn orthographic_maps_film_onto_screen_window() {
+        let window = Bound::<2>::new([(-2.0, 2.0), (-1.0, 1.0)]);
+        let camera = OrthographicCamera::from_transform(
+            Film::new(4, 2),
+            Transform::identity(),
+            window,
+        );
+        assert_eq!(origin(&camera, 0.0, 0.0).v, [-2.0, 1.0, 0.0]);
+        assert_eq!(origin(&camera, 4.0, 2.0).v, [2.0, -1.0, 0.0]);
+        assert_eq!(origin(&camera, 2.0, 1.0).v, [0.0, 0.0, 0.0]);
+
+        // A smaller window zooms in around its own centre.
+        let window = Bound::<2>::new([(0.0, 1.0), (0.5, 1.0)]);
+        let camera = OrthographicCamera::from_transform(
+            Film::new(4, 2),
+            Transform::identity(),
+            window,
+        );
+        assert_eq!(origin(&camera, 0.0, 0.0).v, [0.0, 1.0, 0.0]);
+        assert_eq!(origin(&camera, 2.0, 1.0).v, [0.5, 0.75, 0.0]);
+    }
+
+    #[test]
+    fn roll_survives_motion() {
+        let start = Transform::translate(&Vector3::new([0.0, 0.0, -5.0]));
+        let end = Transform::translate(&Vector3::new([1.0, 0.0, -5.0]));
+        let window = Bound::<2>::new([(-1.0, 1.0), (-1.0, 1.0)]);
+        let camera =
+            || OrthographicCamera::from_transform(Film::new(2, 2), start, window);
+
+        let rolled_first = camera().with_roll(90.0).with_motion(end);
+        let moved_first = camera().with_motion(end).with_roll(90.0);
+        for time in [0.0, 0.5, 0.99] {
+            let sample =
+                CameraSample::new(Vector2::new([2.0, 1.0]), Vector2::zero(), time);
+            let a = rolled_first.generate_ray(&sample).unwrap().o;
+            let b = moved_first.generate_ray(&sample).unwrap().o;
+            let expected = Vector3::new([time, 1.0, -5.0]);
+            assert!((a - expected).magnitude() < 1E-12);
+            assert!((b - expected).magnitude() < 1E-12);
+        }
     }
 }
@@ -1,11 +1,55 @@
-use crate::*;
-use std::{fs::File, io::Write};
+use crate::{
+    rt::filter::Filter,
+    utils::image::{read_hdr, read_pfm, write_hdr, write_pfm, write_png},
+    *,
+};
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Write},
+    ops::Range,
+    path::Path,
+};
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMap {
+    pub fn apply(&self, x: Float) -> Float {
+        let x = x.max(0.0);
+        match self {
+            ToneMap::Clamp => x.min(1.0),
+            ToneMap::Reinhard => x / (1.0 + x),
+            // Narkowicz's fit of the ACES filmic reference rendering transform.
+            ToneMap::Aces => {
+                ((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+pub fn srgb_encode(x: Float) -> Float {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Pixels hold the weighted sum of their samples and the sum of the weights, and
+// are resolved when read.
 #[derive(Clone, Debug)]
 pub struct Film {
     width: usize,
     height: usize,
     data: Vec<Vector<3>>,
+    weights: Vec<Float>,
+    filter: Filter,
+    tone_map: ToneMap,
+    exposure: Float,
 }
 
 impl Film {
@@ -15,15 +59,83 @@ impl Film {
             width,
             height,
             data,
+            weights: vec![0.0; width * height],
+            filter: Filter::default(),
+            tone_map: ToneMap::Clamp,
+            exposure: 0.0,
         }
     }
 
+    pub fn with_filter(mut self, filter: Filter) -> Film {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_tone_map(mut self, tone_map: ToneMap) -> Film {
+        self.tone_map = tone_map;
+        self
+    }
+
+    pub fn with_exposure(mut self, stops: Float) -> Film {
+        self.exposure = stops;
+        self
+    }
+
     pub fn write(&mut self, x: usize, y: usize, value: Vector<3>) {
         self.data[x + y * self.width] = value;
+        self.weights[x + y * self.width] = 1.0;
     }
 
     pub fn read(&self, x: usize, y: usize) -> Vector<3> {
-        self.data[x + y * self.width]
+        self.resolve(x + y * self.width)
+    }
+
+    // `p` is a continuous raster position, pixel (x, y) covers [x, x + 1) x
+    // [y, y + 1). The sample contributes to every pixel whose centre lies within
+    // the filter radius.
+    pub fn add_sample(&mut self, p: &Vector2, value: Vector<3>) {
+        let region = (0..self.width, 0..self.height);
+        splat(
+            &self.filter,
+            &region,
+            &mut self.data,
+            &mut self.weights,
+            p,
+            value,
+        );
+    }
+
+    // A tile receives the samples taken inside the pixels `x` x `y` and covers
+    // every pixel those samples can reach through the filter.
+    pub fn tile(&self, x: Range<usize>, y: Range<usize>) -> FilmTile {
+        let radius = self.filter.radius;
+        let reach = |start: usize, end: usize, r: Float, size: usize| {
+            let min = (start as Float - 0.5 - r).ceil().max(0.0) as usize;
+            let max = ((end as Float - 0.5 + r).floor().max(0.0) as usize + 1).min(size);
+            min..max.max(min)
+        };
+        let region = (
+            reach(x.start, x.end, radius[0], self.width),
+            reach(y.start, y.end, radius[1], self.height),
+        );
+        let len = region.0.len() * region.1.len();
+        FilmTile {
+            region,
+            data: vec![Vector::<3>::zero(); len],
+            weights: vec![0.0; len],
+            filter: self.filter,
+        }
+    }
+
+    pub fn merge(&mut self, tile: &FilmTile) {
+        let (x, y) = &tile.region;
+        for (j, py) in y.clone().enumerate() {
+            for (i, px) in x.clone().enumerate() {
+                let (src, dst) = (i + j * x.len(), px + py * self.width);
+                self.data[dst] = self.data[dst] + tile.data[src];
+                self.weights[dst] += tile.weights[src];
+            }
+        }
     }
 
     pub fn size(&self) -> (usize, usize) {
@@ -34,25 +146,161 @@ impl Film {
         self.width as Float / self.height as Float
     }
 
-    pub fn save(&self, path: &str) -> Result<(), std::io::Error> {
-        let mut file = File::create(path).expect("Cannot create file.");
-        file.write_all(format!("P6 {} {} 255\n", self.width, self.height).as_bytes())
-            .expect("Cannot write data to file.");
+    pub fn pixels(&self) -> Vec<Vector<3>> {
+        (0..self.data.len()).map(|i| self.resolve(i)).collect()
+    }
 
+    // Filters with negative lobes can leave a pixel with a weight sum of zero or
+    // below, which would blow up or flip its value, so it is treated as empty.
+    // Small positive sums, such as from the tail of a wide filter, are kept.
+    fn resolve(&self, i: usize) -> Vector<3> {
+        if self.weights[i] <= 0.0 {
+            Vector::<3>::zero()
+        } else {
+            self.data[i] / self.weights[i]
+        }
+    }
+
+    // The format is chosen by extension: `.pfm` and `.hdr` keep the linear HDR
+    // values, `.png` and anything else (PPM) are tone mapped and sRGB encoded.
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        match extension(path).as_deref() {
+            Some("pfm") => write_pfm(path, self.width, self.height, &self.pixels()),
+            Some("hdr") => write_hdr(path, self.width, self.height, &self.pixels()),
+            Some("png") => write_png(path, self.width, self.height, &self.to_rgb8()),
+            _ => self.save_ppm(path),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Film, Error> {
+        let (width, height, data) = match extension(path).as_deref() {
+            Some("pfm") => read_pfm(path)?,
+            Some("hdr") => read_hdr(path)?,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Cannot load {path}, only .pfm and .hdr are supported."),
+                ))
+            }
+        };
+
+        let mut film = Film::new(width, height);
+        film.data = data;
+        film.weights = vec![1.0; width * height];
+        Ok(film)
+    }
+
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let scale = 2.0_f64.powf(self.exposure);
         let mut content = Vec::with_capacity(self.width * self.height * 3);
-        self.data
-            .iter()
-            .map(|v| {
-                let b = Bound::<3>::new([(0.0, 1.0), (0.0, 1.0), (0.0, 1.0)]);
-                let v = &b.clamp(v);
-                let r = (v[0] * 255.999) as u8;
-                let g = (v[1] * 255.999) as u8;
-                let b = (v[2] * 255.999) as u8;
-                content.extend(vec![r, g, b]);
-            })
-            .for_each(drop);
-        file.write_all(&content)
-            .expect("Cannot write data to file.");
-        Ok(())
+        for v in self.pixels() {
+            for c in v.v {
+                let c = srgb_encode(self.tone_map.apply(c * scale).min(1.0));
+                content.push((c * 255.0 + 0.5) as u8);
+            }
+        }
+        content
+    }
+
+    fn save_ppm(&self, path: &str) -> Result<(), Error> {
+        let mut file = File::create(path)?;
+        file.write_all(format!("P6 {} {} 255\n", self.width, self.height).as_bytes())?;
+        file.write_all(&self.to_rgb8())
+    }
+}
+
+// Unresolved sums over a rectangle of the film, filled by one thread and merged
+// back afterwards.
+pub struct FilmTile {
+    region: (Range<usize>, Range<usize>),
+    data: Vec<Vector<3>>,
+    weights: Vec<Float>,
+    filter: Filter,
+}
+
+impl FilmTile {
+    pub fn add_sample(&mut self, p: &Vector2, value: Vector<3>) {
+        splat(
+            &self.filter,
+            &self.region,
+            &mut self.data,
+            &mut self.weights,
+            p,
+            value,
+        );
+    }
+}
+
+// Accumulates the sample into the pixels of `region` within the filter radius.
+// `data` and `weights` are laid out in rows of the region's width.
+fn splat(
+    filter: &Filter,
+    region: &(Range<usize>, Range<usize>),
+    data: &mut [Vector<3>],
+    weights: &mut [Float],
+    p: &Vector2,
+    value: Vector<3>,
+) {
+    let (rx, ry) = region;
+    let radius = filter.radius;
+    let x_0 = ((p[0] - 0.5 - radius[0]).ceil().max(0.0) as usize).max(rx.start);
+    let y_0 = ((p[1] - 0.5 - radius[1]).ceil().max(0.0) as usize).max(ry.start);
+    let x_1 = (p[0] - 0.5 + radius[0]).floor();
+    let y_1 = (p[1] - 0.5 + radius[1]).floor();
+    if x_1 < 0.0 || y_1 < 0.0 {
+        return;
+    }
+
+    for y in y_0..(y_1 as usize + 1).min(ry.end) {
+        for x in x_0..(x_1 as usize + 1).min(rx.end) {
+            let offset = Vector2::new([x as Float + 0.5 - p[0], y as Float + 0.5 - p[1]]);
+            let weight = filter.evaluate(&offset);
+            if weight != 0.0 {
+                let i = (x - rx.start) + (y - ry.start) * rx.len();
+                data[i] = data[i] + value * weight;
+                weights[i] += weight;
+            }
+        }
+    }
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::filter::FilterKind;
+
+    #[test]
+    fn resolve_ignores_non_positive_weights() {
+        let mut film = Film::new(4, 1);
+        film.data = vec![Vector3::new([2.0, 4.0, 6.0]); 4];
+        film.data[1] = Vector3::new([2.0, 4.0, 6.0]) * 1E-9;
+        film.weights = vec![2.0, 2E-9, 0.0, -0.5];
+
+        let pixels = film.pixels();
+        assert_eq!(pixels[0].v, [1.0, 2.0, 3.0]);
+        assert!((pixels[1] - Vector3::new([1.0, 2.0, 3.0])).magnitude() < 1E-12);
+        assert_eq!(pixels[2].v, [0.0; 3]);
+        assert_eq!(pixels[3].v, [0.0; 3]);
+    }
+
+    #[test]
+    fn filter_tails_keep_their_energy() {
+        let mut film = Film::new(8, 1).with_filter(Filter::new(
+            FilterKind::Gaussian { sigma: 0.35 },
+            Vector2::new([3.0, 3.0]),
+        ));
+        film.add_sample(&Vector2::new([0.5, 0.5]), Vector3::new([1.0, 1.0, 1.0]));
+        // The farthest pixel only sees the tail of the filter but still resolves to
+        // the sample's value.
+        let pixels = film.pixels();
+        let last = (0..8).rev().find(|&x| film.weights[x] > 0.0).unwrap();
+        assert!(film.weights[last] < 1E-6);
+        assert!((pixels[last][0] - 1.0).abs() < 1E-9);
     }
 }
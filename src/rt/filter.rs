@@ -0,0 +1,84 @@
+use crate::*;
+use std::f64::consts::PI;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian { sigma: Float },
+    Mitchell { b: Float, c: Float },
+    Lanczos { tau: Float },
+}
+
+// Reconstruction filters are separable and evaluated at the offset between a
+// sample and a pixel centre in raster space. Weights outside the radius are zero.
+#[derive(Copy, Clone, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: Vector2,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: Vector2) -> Filter {
+        Filter { kind, radius }
+    }
+
+    pub fn evaluate(&self, p: &Vector2) -> Float {
+        self.evaluate_1d(p[0], self.radius[0]) * self.evaluate_1d(p[1], self.radius[1])
+    }
+
+    fn evaluate_1d(&self, x: Float, radius: Float) -> Float {
+        let x = x.abs();
+        if x > radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => radius - x,
+            // Shifted down so the filter falls off to zero at the radius.
+            FilterKind::Gaussian { sigma } => {
+                (gaussian(x, sigma) - gaussian(radius, sigma)).max(0.0)
+            }
+            FilterKind::Mitchell { b, c } => mitchell(2.0 * x / radius, b, c),
+            FilterKind::Lanczos { tau } => sinc(x) * sinc(x / tau),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::new(FilterKind::Box, Vector2::new([0.5, 0.5]))
+    }
+}
+
+fn gaussian(x: Float, sigma: Float) -> Float {
+    (-x * x / (2.0 * sigma * sigma)).exp()
+}
+
+// The cubic is defined over [-2, 2], so the offset is remapped from the radius.
+fn mitchell(x: Float, b: Float, c: Float) -> Float {
+    let x = x.abs();
+    if x > 2.0 {
+        0.0
+    } else if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+fn sinc(x: Float) -> Float {
+    if x.abs() < 1E-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
@@ -0,0 +1,507 @@
+use crate::{
+    lm::sampling::power_heuristic,
+    rt::{
+        camera::{Camera, CameraSample},
+        film::{Film, FilmTile},
+        light::LightSample,
+        material::Material,
+        sampler::Sampler,
+    },
+    utils::statistics::Progress,
+    *,
+};
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+const TILE_SIZE: usize = 16;
+const SHADOW_EPSILON: Float = 1E-4;
+
+pub trait Integrator: Sync {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Vector3;
+
+    fn render(
+        &self,
+        camera: &dyn Camera,
+        scene: &Scene,
+        film: &mut Film,
+        sampler: &dyn Sampler,
+    ) {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        self.render_with_threads(camera, scene, film, sampler, threads);
+    }
+
+    // Tiles are handed out to the workers as they become free but merged in a
+    // fixed order, so the image does not depend on the number of threads.
+    fn render_with_threads(
+        &self,
+        camera: &dyn Camera,
+        scene: &Scene,
+        film: &mut Film,
+        sampler: &dyn Sampler,
+        threads: usize,
+    ) {
+        let (width, height) = film.size();
+        let tiles = tiles(width, height);
+        let next = AtomicUsize::new(0);
+        let progress = Progress::new("Rendering", tiles.len());
+
+        let target = &*film;
+        let mut rendered = thread::scope(|s| {
+            let workers = (0..threads.clamp(1, tiles.len().max(1)))
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut sampler = sampler.clone_sampler();
+                        let mut finished = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let (x, y) = match tiles.get(index) {
+                                Some(tile) => tile,
+                                None => break,
+                            };
+                            let mut tile = target.tile(x.clone(), y.clone());
+                            render_tile(
+                                self,
+                                camera,
+                                scene,
+                                &mut tile,
+                                sampler.as_mut(),
+                                x,
+                                y,
+                            );
+                            finished.push((index, tile));
+                            progress.update(1);
+                        }
+                        finished
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().expect("Render thread panicked."))
+                .collect::<Vec<(usize, FilmTile)>>()
+        });
+        progress.finish();
+
+        rendered.sort_by_key(|(index, _)| *index);
+        for (_, tile) in &rendered {
+            film.merge(tile);
+        }
+    }
+}
+
+fn tiles(width: usize, height: usize) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            tiles.push((
+                x..(x + TILE_SIZE).min(width),
+                y..(y + TILE_SIZE).min(height),
+            ));
+        }
+    }
+    tiles
+}
+
+fn render_tile<I: Integrator + ?Sized>(
+    integrator: &I,
+    camera: &dyn Camera,
+    scene: &Scene,
+    tile: &mut FilmTile,
+    sampler: &mut dyn Sampler,
+    x: &Range<usize>,
+    y: &Range<usize>,
+) {
+    let spp = sampler.samples_per_pixel().max(1);
+    let differential_scale = (spp as Float).sqrt().recip().max(0.125);
+    for j in y.clone() {
+        for i in x.clone() {
+            for s in 0..spp {
+                sampler.start_pixel_sample((i, j), s);
+                let p = Vector2::new([i as Float, j as Float]) + sampler.get_pixel_2d();
+                let sample = CameraSample::new(p, sampler.get_2d(), sampler.get_1d());
+                let value = match camera.generate_ray_differential(&sample) {
+                    Some(mut ray) => {
+                        ray.scale_differentials(differential_scale);
+                        integrator.li(&ray, scene, sampler)
+                    }
+                    None => Vector3::zero(),
+                };
+                tile.add_sample(&p, value);
+            }
+        }
+    }
+}
+
+pub struct PathIntegrator {
+    pub max_depth: usize,
+    pub roulette_depth: usize,
+    pub background: Vector3,
+}
+
+impl PathIntegrator {
+    pub fn new(max_depth: usize) -> PathIntegrator {
+        PathIntegrator {
+            max_depth,
+            roulette_depth: 3,
+            background: Vector3::zero(),
+        }
+    }
+
+    pub fn with_background(mut self, background: Vector3) -> PathIntegrator {
+        self.background = background;
+        self
+    }
+}
+
+// Emission is gathered both by sampling a light at every vertex and by the BSDF
+// sampled rays hitting emitters, and the two are combined with multiple
+// importance sampling.
+impl Integrator for PathIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Vector3 {
+        let mut radiance = Vector3::zero();
+        let mut throughput = Vector3::new([1.0, 1.0, 1.0]);
+        let mut ray = Ray::new(ray.o, ray.d)
+            .with_time(ray.time)
+            .with_differentials(ray.differentials);
+        // The vertex the ray left from and the BSDF density of its direction,
+        // `None` for camera rays.
+        let mut previous: Option<(Vector3, Float)> = None;
+
+        for depth in 0..self.max_depth {
+            let bound = Interval::new([(0.0, Float::INFINITY)]);
+            let intersection = match scene.intersect(&ray, &bound) {
+                Some(intersection) => intersection,
+                None => {
+                    let escaped =
+                        escaped_radiance(scene, &ray, previous.map(|(_, pdf)| pdf));
+                    radiance = radiance + throughput * (self.background + escaped);
+                    break;
+                }
+            };
+
+            let wo = -ray.d.normalize();
+            if let Some(light) = &intersection.light {
+                let weight = match previous {
+                    Some((point, pdf)) => {
+                        let light_pdf = light.pdf_li(&point, &intersection)
+                            / scene.lights.len() as Float;
+                        power_heuristic(1, pdf, 1, light_pdf)
+                    }
+                    None => 1.0,
+                };
+                radiance = radiance + throughput * light.l(&intersection, &wo) * weight;
+            }
+            // Paths have at most `max_depth` segments. Emission at the last vertex
+            // still counts, but a light sample from it would add one more.
+            if depth + 1 == self.max_depth {
+                break;
+            }
+
+            let material = match &intersection.material {
+                Some(material) => material,
+                None => break,
+            };
+            let normal = facing(&intersection.shading.normal, &wo);
+            radiance = radiance
+                + throughput
+                    * sample_one_light(
+                        scene,
+                        &intersection,
+                        material.as_ref(),
+                        &wo,
+                        &normal,
+                        sampler,
+                        ray.time,
+                    );
+
+            let u = sampler.get_2d();
+            let sample = match material.sample_f(&wo, &normal, &u) {
+                Some(sample) => sample,
+                None => break,
+            };
+            throughput =
+                throughput * sample.f * (sample.wi.dot(&normal).abs() / sample.pdf);
+            previous = Some((intersection.point, sample.pdf));
+
+            if depth + 1 >= self.roulette_depth {
+                let max = throughput[0].max(throughput[1]).max(throughput[2]);
+                let q = (1.0 - max).max(0.05);
+                if sampler.get_1d() < q {
+                    break;
+                }
+                throughput = throughput / (1.0 - q);
+            }
+
+            ray = intersection.spawn_ray(sample.wi).with_time(ray.time);
+        }
+
+        radiance
+    }
+}
+
+// Whitted-style preview: surfaces are lit by the scene's lights with shadows but
+// without any indirect bounce.
+pub struct DirectLightingIntegrator {
+    pub background: Vector3,
+}
+
+impl DirectLightingIntegrator {
+    pub fn new() -> DirectLightingIntegrator {
+        DirectLightingIntegrator {
+            background: Vector3::zero(),
+        }
+    }
+
+    pub fn with_background(mut self, background: Vector3) -> DirectLightingIntegrator {
+        self.background = background;
+        self
+    }
+}
+
+impl Default for DirectLightingIntegrator {
+    fn default() -> DirectLightingIntegrator {
+        DirectLightingIntegrator::new()
+    }
+}
+
+impl Integrator for DirectLightingIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Vector3 {
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        let intersection = match scene.intersect(ray, &bound) {
+            Some(intersection) => intersection,
+            None => return self.background + escaped_radiance(scene, ray, None),
+        };
+
+        let wo = -ray.d.normalize();
+        let mut radiance = match &intersection.light {
+            Some(light) => light.l(&intersection, &wo),
+            None => Vector3::zero(),
+        };
+        let material = match &intersection.material {
+            Some(material) => material,
+            None => return radiance,
+        };
+
+        let normal = facing(&intersection.shading.normal, &wo);
+        for light in &scene.lights {
+            let u = sampler.get_2d();
+            let sample = match light.sample_li(&intersection.point, &u) {
+                Some(sample) if sample.pdf > 0.0 => sample,
+                _ => continue,
+            };
+            let f = material.f(&wo, &sample.wi, &normal);
+            if f.v == [0.0; 3] || !unoccluded(scene, &intersection, &sample, ray.time) {
+                continue;
+            }
+            radiance = radiance
+                + f * sample.radiance * (sample.wi.dot(&normal).abs() / sample.pdf);
+        }
+
+        radiance
+    }
+}
+
+// Estimates direct lighting from one light picked uniformly, weighted against
+// BSDF sampling unless the light cannot be hit by rays.
+fn sample_one_light(
+    scene: &Scene,
+    intersection: &Intersection,
+    material: &dyn Material,
+    wo: &Vector3,
+    normal: &Vector3,
+    sampler: &mut dyn Sampler,
+    time: Float,
+) -> Vector3 {
+    let count = scene.lights.len();
+    if count == 0 {
+        return Vector3::zero();
+    }
+    let index = ((sampler.get_1d() * count as Float) as usize).min(count - 1);
+    let light = &scene.lights[index];
+
+    let u = sampler.get_2d();
+    let sample = match light.sample_li(&intersection.point, &u) {
+        Some(sample) if sample.pdf > 0.0 => sample,
+        _ => return Vector3::zero(),
+    };
+    let f = material.f(wo, &sample.wi, normal);
+    if f.v == [0.0; 3] || !unoccluded(scene, intersection, &sample, time) {
+        return Vector3::zero();
+    }
+
+    let light_pdf = sample.pdf / count as Float;
+    let weight = if light.is_delta() {
+        1.0
+    } else {
+        power_heuristic(1, light_pdf, 1, material.pdf(wo, &sample.wi, normal))
+    };
+    f * sample.radiance * (sample.wi.dot(normal).abs() * weight / light_pdf)
+}
+
+// Radiance from the lights at infinity along a ray that left the scene. When the
+// ray was sampled from a BSDF with density `bsdf_pdf`, it is weighted against
+// sampling the lights directly.
+fn escaped_radiance(scene: &Scene, ray: &Ray, bsdf_pdf: Option<Float>) -> Vector3 {
+    let direction = ray.d.normalize();
+    let mut radiance = Vector3::zero();
+    for light in &scene.lights {
+        let le = light.le(ray);
+        if le.v == [0.0; 3] {
+            continue;
+        }
+        let weight = match bsdf_pdf {
+            Some(pdf) => {
+                let light_pdf = light.pdf_le(&direction) / scene.lights.len() as Float;
+                power_heuristic(1, pdf, 1, light_pdf)
+            }
+            None => 1.0,
+        };
+        radiance = radiance + le * weight;
+    }
+    radiance
+}
+
+// Flips the shading normal into the hemisphere of `wo`.
+fn facing(normal: &Vector3, wo: &Vector3) -> Vector3 {
+    if normal.dot(wo) < 0.0 {
+        -*normal
+    } else {
+        *normal
+    }
+}
+
+// Shadow rays towards a light position stop just short of it so that the light's
+// own geometry does not occlude it.
+fn unoccluded(
+    scene: &Scene,
+    intersection: &Intersection,
+    sample: &LightSample,
+    time: Float,
+) -> bool {
+    let (ray, bound) = match &sample.point {
+        Some(point) => (
+            intersection.spawn_ray_to(point),
+            Interval::new([(0.0, 1.0 - SHADOW_EPSILON)]),
+        ),
+        None => (
+            intersection.spawn_ray(sample.wi),
+            Interval::new([(0.0, Float::INFINITY)]),
+        ),
+    };
+    !scene.intersect_p(&ray.with_time(time), &bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::{
+        camera::PerspectiveCamera,
+        light::{DiffuseAreaLight, Light},
+        material::Lambertian,
+        sampler::IndependentSampler,
+    };
+    use std::sync::Arc;
+
+    // A square light facing down onto a diffuse floor.
+    fn scene() -> Scene {
+        let material: Arc<dyn Material> =
+            Arc::new(Lambertian::new(Vector3::new([0.5, 0.5, 0.5])));
+        let light = DiffuseAreaLight::new(
+            Quad::new(
+                Vector3::new([-1.0, -1.0, 2.0]),
+                Vector3::new([0.0, 2.0, 0.0]),
+                Vector3::new([2.0, 0.0, 0.0]),
+            ),
+            Transform::identity(),
+            Vector3::new([4.0, 4.0, 4.0]),
+        );
+        let floor = Quad::new(
+            Vector3::new([-5.0, -5.0, 0.0]),
+            Vector3::new([10.0, 0.0, 0.0]),
+            Vector3::new([0.0, 10.0, 0.0]),
+        );
+        Scene::new()
+            .push_area_light(light, material.clone())
+            .push_transformed(Primitive::new(floor, material), Transform::identity())
+    }
+
+    // Emits the screen-space derivatives of the surface it is attached to.
+    struct Footprint;
+
+    impl Light for Footprint {
+        fn sample_li(&self, _point: &Vector3, _u: &Vector2) -> Option<LightSample> {
+            None
+        }
+
+        fn l(&self, intersection: &Intersection, _w: &Vector3) -> Vector3 {
+            Vector3::new([
+                intersection.dudx.abs() + intersection.dvdx.abs(),
+                intersection.dudy.abs() + intersection.dvdy.abs(),
+                intersection.dpdx.magnitude() + intersection.dpdy.magnitude(),
+            ])
+        }
+
+        fn is_delta(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn camera_rays_keep_their_differentials() {
+        let material: Arc<dyn Material> =
+            Arc::new(Lambertian::new(Vector3::new([0.5, 0.5, 0.5])));
+        let plane = Plane::new(Vector3::zero(), Vector3::new([0.0, 1.0, 0.0]));
+        let scene = Scene::new().push_transformed(
+            Primitive::new(plane, material).with_light(Arc::new(Footprint)),
+            Transform::identity(),
+        );
+        let mut film = Film::new(8, 8);
+        let camera = PerspectiveCamera::build(
+            film.clone(),
+            Vector3::new([0.0, 2.0, -4.0]),
+            Vector3::zero(),
+            Vector3::new([0.0, 1.0, 0.0]),
+            40.0,
+        );
+
+        PathIntegrator::new(1).render_with_threads(
+            &camera,
+            &scene,
+            &mut film,
+            &IndependentSampler::new(1, 3),
+            1,
+        );
+        for pixel in film.pixels() {
+            assert!(pixel.v.iter().all(|&c| c > 0.0), "{pixel:?}");
+        }
+    }
+
+    #[test]
+    fn max_depth_limits_the_path_length() {
+        let scene = scene();
+        let mut sampler = IndependentSampler::new(1, 5);
+        let background = Vector3::new([0.25, 0.25, 0.25]);
+        let up = Vector3::new([0.0, 0.0, 1.0]);
+        let to_light = Ray::new(Vector3::new([0.0, 0.0, 1.0]), up);
+        let to_floor = Ray::new(Vector3::new([3.0, 3.0, 1.0]), -up);
+        let away = Ray::new(Vector3::new([3.0, 3.0, 1.0]), up);
+
+        let li = |depth: usize, ray: &Ray, sampler: &mut IndependentSampler| {
+            PathIntegrator::new(depth)
+                .with_background(background)
+                .li(ray, &scene, sampler)
+        };
+        for ray in [&to_light, &to_floor, &away] {
+            assert_eq!(li(0, ray, &mut sampler).v, [0.0; 3]);
+        }
+        // A single segment sees emitters and the background but nothing they light.
+        assert_eq!(li(1, &to_light, &mut sampler).v, [4.0; 3]);
+        assert_eq!(li(1, &away, &mut sampler).v, background.v);
+        assert_eq!(li(1, &to_floor, &mut sampler).v, [0.0; 3]);
+        assert!(li(2, &to_floor, &mut sampler)[0] > 0.0);
+    }
+}
@@ -0,0 +1,568 @@
+use crate::lm::sampling::Distribution2D;
+use crate::rt::film::Film;
+use crate::shapes::shape::{area_to_solid_angle, ShapeSample};
+use crate::*;
+use std::f64::consts::PI;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+
+// Incident radiance from a light towards a receiving point. `wi` points from the
+// receiver to the light, `point` is `None` for lights infinitely far away.
+pub struct LightSample {
+    pub radiance: Vector3,
+    pub wi: Vector3,
+    pub pdf: Float,
+    pub point: Option<Vector3>,
+}
+
+pub trait Light: Send + Sync {
+    fn sample_li(&self, point: &Vector3, u: &Vector2) -> Option<LightSample>;
+
+    // The solid angle density at `point` of `sample_li` choosing the surface point
+    // of `intersection`.
+    fn pdf_li(&self, _point: &Vector3, _intersection: &Intersection) -> Float {
+        0.0
+    }
+
+    // Radiance leaving the light's surface at `intersection` in direction `w`.
+    fn l(&self, _intersection: &Intersection, _w: &Vector3) -> Vector3 {
+        Vector3::zero()
+    }
+
+    // Radiance arriving along a ray that left the scene, for lights at infinity.
+    fn le(&self, _ray: &Ray) -> Vector3 {
+        Vector3::zero()
+    }
+
+    // The solid angle density of `sample_li` choosing `direction` towards a light
+    // at infinity.
+    fn pdf_le(&self, _direction: &Vector3) -> Float {
+        0.0
+    }
+
+    // Lights described by a delta distribution are never hit by rays, so they can
+    // only be sampled.
+    fn is_delta(&self) -> bool;
+}
+
+pub struct PointLight {
+    pub position: Vector3,
+    pub intensity: Vector3,
+}
+
+impl PointLight {
+    pub fn new(position: Vector3, intensity: Vector3) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_li(&self, point: &Vector3, _u: &Vector2) -> Option<LightSample> {
+        let (wi, distance_squared) = towards(point, &self.position)?;
+        Some(LightSample {
+            radiance: self.intensity / distance_squared,
+            wi,
+            pdf: 1.0,
+            point: Some(self.position),
+        })
+    }
+}
+
+// Emits inside a cone around `direction`. The intensity is constant up to the
+// falloff angle and fades out smoothly towards the cone angle.
+pub struct SpotLight {
+    pub position: Vector3,
+    pub direction: Vector3,
+    pub intensity: Vector3,
+    cos_falloff_start: Float,
+    cos_falloff_end: Float,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Vector3,
+        target: Vector3,
+        intensity: Vector3,
+        cone_degrees: Float,
+        falloff_start_degrees: Float,
+    ) -> SpotLight {
+        let falloff_start_degrees = falloff_start_degrees.min(cone_degrees);
+        SpotLight {
+            position,
+            direction: (target - position).normalize(),
+            intensity,
+            cos_falloff_start: falloff_start_degrees.to_radians().cos(),
+            cos_falloff_end: cone_degrees.to_radians().cos(),
+        }
+    }
+
+    fn falloff(&self, w: &Vector3) -> Float {
+        let cos_theta = w.dot(&self.direction);
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        let width = self.cos_falloff_start - self.cos_falloff_end;
+        if width <= 0.0 {
+            return 0.0;
+        }
+        let t = ((cos_theta - self.cos_falloff_end) / width).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_li(&self, point: &Vector3, _u: &Vector2) -> Option<LightSample> {
+        let (wi, distance_squared) = towards(point, &self.position)?;
+        let falloff = self.falloff(&-wi);
+        if falloff == 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            radiance: self.intensity * (falloff / distance_squared),
+            wi,
+            pdf: 1.0,
+            point: Some(self.position),
+        })
+    }
+}
+
+// A distant light such as the sun, `direction` is the direction the light travels.
+pub struct DirectionalLight {
+    pub direction: Vector3,
+    pub radiance: Vector3,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vector3, radiance: Vector3) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.normalize(),
+            radiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_li(&self, _point: &Vector3, _u: &Vector2) -> Option<LightSample> {
+        Some(LightSample {
+            radiance: self.radiance,
+            wi: -self.direction,
+            pdf: 1.0,
+            point: None,
+        })
+    }
+}
+
+// Emits `radiance` uniformly from the front of a shape, or both sides if
+// `two_sided` is set. The shape is sampled in its object space, the densities
+// are carried over to world space through the area change of `to_world`, which
+// keeps them correct for any transform.
+pub struct DiffuseAreaLight {
+    pub shape: Arc<dyn Shape>,
+    pub to_world: Transform,
+    pub radiance: Vector3,
+    pub two_sided: bool,
+}
+
+impl DiffuseAreaLight {
+    pub fn new(
+        shape: impl Shape + 'static,
+        to_world: Transform,
+        radiance: Vector3,
+    ) -> DiffuseAreaLight {
+        DiffuseAreaLight {
+            shape: Arc::new(shape),
+            to_world,
+            radiance,
+            two_sided: false,
+        }
+    }
+
+    pub fn with_two_sided(mut self, two_sided: bool) -> DiffuseAreaLight {
+        self.two_sided = two_sided;
+        self
+    }
+
+    fn emitted(&self, normal: &Vector3, w: &Vector3) -> Vector3 {
+        if !self.two_sided && normal.dot(w) <= 0.0 {
+            return Vector3::zero();
+        }
+        self.radiance
+    }
+
+    // Turns a solid angle density at `reference` in object space into one at
+    // `point` in world space, by way of the area densities on both sides.
+    fn world_pdf(
+        &self,
+        reference: &Vector3,
+        sample: &ShapeSample,
+        point: &Vector3,
+        world_sample: (&Vector3, &Vector3),
+    ) -> Float {
+        let d = sample.point - *reference;
+        let distance_squared = d.dot(&d);
+        let area_pdf = sample.pdf * sample.normal.dot(&d).abs()
+            / (distance_squared * distance_squared.sqrt());
+
+        let (s, t) = sample.normal.normalize().coordinate_system();
+        let area_scale = self
+            .to_world
+            .apply_vector(&s)
+            .cross(&self.to_world.apply_vector(&t))
+            .magnitude();
+        let (p, n) = world_sample;
+        let pdf = area_to_solid_angle(area_pdf / area_scale, point, p, n);
+        if pdf.is_finite() {
+            pdf
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Light for DiffuseAreaLight {
+    fn sample_li(&self, point: &Vector3, u: &Vector2) -> Option<LightSample> {
+        let reference = self.to_world.apply_inverse_point(point);
+        let sample = self.shape.sample_from(&reference, u)?;
+        let p = self.to_world.apply_point(&sample.point);
+        let n = self.to_world.apply_normal(&sample.normal).normalize();
+        let (wi, _) = towards(point, &p)?;
+        let radiance = self.emitted(&n, &-wi);
+        let pdf = self.world_pdf(&reference, &sample, point, (&p, &n));
+        if pdf == 0.0 || radiance.v == [0.0; 3] {
+            return None;
+        }
+
+        Some(LightSample {
+            radiance,
+            wi,
+            pdf,
+            point: Some(p),
+        })
+    }
+
+    fn pdf_li(&self, point: &Vector3, intersection: &Intersection) -> Float {
+        let reference = self.to_world.apply_inverse_point(point);
+        let p = self.to_world.apply_inverse_point(&intersection.point);
+        let n = self
+            .to_world
+            .apply_inverse_normal(&intersection.normal)
+            .normalize();
+        let sample = ShapeSample {
+            point: p,
+            normal: n,
+            pdf: self.shape.pdf_from(&reference, &p, &n),
+        };
+        if sample.pdf == 0.0 {
+            return 0.0;
+        }
+        self.world_pdf(
+            &reference,
+            &sample,
+            point,
+            (&intersection.point, &intersection.normal),
+        )
+    }
+
+    fn l(&self, intersection: &Intersection, w: &Vector3) -> Vector3 {
+        self.emitted(&intersection.normal, w)
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+// Surrounds the scene with an equirectangular radiance map laid out like the
+// images of `EquirectangularCamera`: +y is up and the centre of the image looks
+// along +z. `to_world` rotates the map. Directions are importance sampled by the
+// luminance of the pixels, weighted by the solid angle they cover.
+pub struct EnvironmentLight {
+    pub image: Film,
+    pub to_world: Transform,
+    pub scale: Float,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentLight {
+    // Fails for an empty image, which has no directions to look up or sample.
+    pub fn new(image: Film) -> Result<EnvironmentLight, Error> {
+        let (width, height) = image.size();
+        if width == 0 || height == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Environment map of {width}x{height} pixels is empty."),
+            ));
+        }
+
+        let mut func = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as Float + 0.5) / height as Float).sin();
+            for x in 0..width {
+                func.push(luminance(&image.read(x, y)) * sin_theta);
+            }
+        }
+
+        Ok(EnvironmentLight {
+            image,
+            to_world: Transform::identity(),
+            scale: 1.0,
+            distribution: Distribution2D::new(&func, width, height),
+        })
+    }
+
+    pub fn load(path: &str) -> Result<EnvironmentLight, Error> {
+        EnvironmentLight::new(Film::load(path)?)
+    }
+
+    pub fn with_rotation(mut self, to_world: Transform) -> EnvironmentLight {
+        self.to_world = to_world;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Float) -> EnvironmentLight {
+        self.scale = scale;
+        self
+    }
+
+    // Pixels are looked up without filtering so that the radiance is constant
+    // over the cells of the sampling distribution.
+    fn lookup(&self, uv: &Vector2) -> Vector3 {
+        let (width, height) = self.image.size();
+        let x = ((uv[0] * width as Float).max(0.0) as usize).min(width - 1);
+        let y = ((uv[1] * height as Float).max(0.0) as usize).min(height - 1);
+        self.image.read(x, y) * self.scale
+    }
+
+    // Image coordinates of a world space direction and the sine of its polar
+    // angle.
+    fn direction_to_uv(&self, direction: &Vector3) -> (Vector2, Float) {
+        let w = self.to_world.apply_inverse_vector(direction).normalize();
+        let theta = w[1].clamp(-1.0, 1.0).acos();
+        let phi = w[0].atan2(w[2]);
+        let uv = Vector2::new([(phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI]);
+        (uv, theta.sin())
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample_li(&self, _point: &Vector3, u: &Vector2) -> Option<LightSample> {
+        let (uv, map_pdf) = self.distribution.sample_continuous(u);
+        let theta = PI * uv[1];
+        let phi = 2.0 * PI * (uv[0] - 0.5);
+        let sin_theta = theta.sin();
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+
+        let local =
+            Vector3::new([sin_theta * phi.sin(), theta.cos(), sin_theta * phi.cos()]);
+        Some(LightSample {
+            radiance: self.lookup(&uv),
+            wi: self.to_world.apply_vector(&local).normalize(),
+            pdf: map_pdf / (2.0 * PI * PI * sin_theta),
+            point: None,
+        })
+    }
+
+    fn le(&self, ray: &Ray) -> Vector3 {
+        self.lookup(&self.direction_to_uv(&ray.d).0)
+    }
+
+    // The map is parameterised over [0, 2 pi] x [0, pi], so the density of a
+    // direction is the image density divided by 2 pi^2 sin theta.
+    fn pdf_le(&self, direction: &Vector3) -> Float {
+        let (uv, sin_theta) = self.direction_to_uv(direction);
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(&uv) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+fn luminance(v: &Vector3) -> Float {
+    0.2126 * v[0] + 0.7152 * v[1] + 0.0722 * v[2]
+}
+
+// The unit direction from `from` to `to` and the squared distance between them.
+fn towards(from: &Vector3, to: &Vector3) -> Option<(Vector3, Float)> {
+    let d = *to - *from;
+    let distance_squared = d.dot(&d);
+    if distance_squared == 0.0 {
+        return None;
+    }
+    Some((d / distance_squared.sqrt(), distance_squared))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lm::rng::Rng;
+    use crate::lm::sampling::uniform_sphere;
+
+    fn stretch() -> Transform {
+        Transform::translate(&Vector3::new([0.5, -1.0, 2.0]))
+            * Transform::rotate_x(30.0)
+            * Transform::rotate_z(-20.0)
+            * Transform::scale(2.0, 0.5, 3.0)
+    }
+
+    fn references() -> [Vector3; 3] {
+        [
+            Vector3::new([0.0, 0.0, 0.0]),
+            Vector3::new([3.0, 2.0, 5.0]),
+            Vector3::new([-1.0, -4.0, 1.0]),
+        ]
+    }
+
+    #[test]
+    fn area_light_densities_follow_the_world_area() {
+        let (corner, u, v) = (
+            Vector3::new([-1.0, -1.0, 0.0]),
+            Vector3::new([2.0, 0.5, 0.0]),
+            Vector3::new([0.0, 2.0, 0.5]),
+        );
+        let to_world = stretch();
+        let light = DiffuseAreaLight::new(
+            Quad::new(corner, u, v),
+            to_world,
+            Vector3::new([1.0, 1.0, 1.0]),
+        )
+        .with_two_sided(true);
+        let world_u = to_world.apply_vector(&u);
+        let world_v = to_world.apply_vector(&v);
+        let world_normal = world_u.cross(&world_v).normalize();
+        let area_pdf = world_u.cross(&world_v).magnitude().recip();
+
+        let scene = Scene::new().push_transformed(light.shape.clone(), to_world);
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        let mut rng = Rng::new(31);
+        for reference in references() {
+            for _ in 0..200 {
+                let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+                let sample = light.sample_li(&reference, &u).unwrap();
+                let p = sample.point.unwrap();
+                let expected =
+                    area_to_solid_angle(area_pdf, &reference, &p, &world_normal);
+                assert!((sample.pdf - expected).abs() < 1E-9 * expected);
+
+                let hit = scene.intersect(&Ray::new(reference, p - reference), &bound);
+                let pdf = light.pdf_li(&reference, &hit.unwrap());
+                assert!((pdf - expected).abs() < 1E-9 * expected);
+            }
+        }
+    }
+
+    // A stretched sphere is sampled through the cone it subtends in object space,
+    // the world space density still has to integrate to one.
+    #[test]
+    fn stretched_sphere_density_integrates_to_one() {
+        let light = DiffuseAreaLight::new(
+            Sphere::new(Vector3::zero(), 1.0),
+            stretch(),
+            Vector3::new([1.0, 1.0, 1.0]),
+        );
+        let scene = Scene::new().push_transformed(light.shape.clone(), light.to_world);
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        let mut rng = Rng::new(32);
+        let count = 100_000;
+        for reference in references() {
+            let mut sum = 0.0;
+            for _ in 0..count {
+                let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+                let ray = Ray::new(reference, uniform_sphere(&u));
+                if let Some(hit) = scene.intersect(&ray, &bound) {
+                    sum += light.pdf_li(&reference, &hit);
+                }
+            }
+            let integral = sum * 4.0 * PI / count as Float;
+            assert!((integral - 1.0).abs() < 0.05, "{integral}");
+        }
+    }
+
+    fn environment() -> EnvironmentLight {
+        let mut image = Film::new(16, 8);
+        for y in 0..8 {
+            for x in 0..16 {
+                let v = 0.1 + (x * y) as Float + if x == 3 { 50.0 } else { 0.0 };
+                image.write(x, y, Vector3::new([v, 0.5 * v, 0.25]));
+            }
+        }
+        EnvironmentLight::new(image)
+            .unwrap()
+            .with_rotation(Transform::rotate_x(40.0) * Transform::rotate_y(-75.0))
+    }
+
+    #[test]
+    fn environment_samples_match_lookups_and_densities() {
+        let light = environment();
+        let mut rng = Rng::new(33);
+        for _ in 0..2000 {
+            let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+            let sample = match light.sample_li(&Vector3::zero(), &u) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let pdf = light.pdf_le(&sample.wi);
+            assert!(
+                (pdf - sample.pdf).abs() < 1E-6 * pdf,
+                "{pdf} != {}",
+                sample.pdf
+            );
+            let le = light.le(&Ray::new(Vector3::zero(), sample.wi));
+            assert_eq!(le.v, sample.radiance.v);
+        }
+    }
+
+    #[test]
+    fn environment_density_integrates_to_one() {
+        let light = environment();
+        let mut rng = Rng::new(34);
+        let count = 100_000;
+        let sum = (0..count)
+            .map(|_| {
+                let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+                light.pdf_le(&uniform_sphere(&u))
+            })
+            .sum::<Float>();
+        let integral = sum * 4.0 * PI / count as Float;
+        assert!((integral - 1.0).abs() < 0.05, "{integral}");
+    }
+
+    #[test]
+    fn empty_environment_maps_are_rejected() {
+        for (width, height) in [(0, 0), (0, 4), (4, 0)] {
+            assert!(EnvironmentLight::new(Film::new(width, height)).is_err());
+        }
+
+        let path =
+            std::env::temp_dir().join(format!("aisth-{}-empty.hdr", std::process::id()));
+        let path = path.to_str().unwrap();
+        Film::new(0, 0).save(path).unwrap();
+        let loaded = EnvironmentLight::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            loaded.err().map(|e| e.kind()),
+            Some(ErrorKind::InvalidInput)
+        );
+    }
+}
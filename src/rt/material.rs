@@ -0,0 +1,67 @@
+use crate::lm::sampling::{cosine_hemisphere, cosine_hemisphere_pdf};
+use crate::*;
+use std::f64::consts::FRAC_1_PI;
+
+pub struct MaterialSample {
+    pub wi: Vector3,
+    pub f: Vector3,
+    pub pdf: Float,
+}
+
+// All directions point away from the surface and `normal` faces the side of `wo`.
+pub trait Material: Send + Sync {
+    fn f(&self, wo: &Vector3, wi: &Vector3, normal: &Vector3) -> Vector3;
+
+    fn sample_f(
+        &self,
+        wo: &Vector3,
+        normal: &Vector3,
+        u: &Vector2,
+    ) -> Option<MaterialSample>;
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3, normal: &Vector3) -> Float;
+}
+
+pub struct Lambertian {
+    pub albedo: Vector3,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Vector3) -> Lambertian {
+        Lambertian { albedo }
+    }
+}
+
+impl Material for Lambertian {
+    fn f(&self, _wo: &Vector3, wi: &Vector3, normal: &Vector3) -> Vector3 {
+        if wi.dot(normal) <= 0.0 {
+            return Vector3::zero();
+        }
+        self.albedo * FRAC_1_PI
+    }
+
+    fn sample_f(
+        &self,
+        wo: &Vector3,
+        normal: &Vector3,
+        u: &Vector2,
+    ) -> Option<MaterialSample> {
+        let local = cosine_hemisphere(u);
+        let (s, t) = normal.coordinate_system();
+        let wi = s * local[0] + t * local[1] + *normal * local[2];
+        let pdf = cosine_hemisphere_pdf(local[2]);
+        if pdf == 0.0 {
+            return None;
+        }
+
+        Some(MaterialSample {
+            wi,
+            f: self.f(wo, &wi, normal),
+            pdf,
+        })
+    }
+
+    fn pdf(&self, _wo: &Vector3, wi: &Vector3, normal: &Vector3) -> Float {
+        cosine_hemisphere_pdf(wi.dot(normal))
+    }
+}
@@ -1,3 +1,8 @@
 pub mod camera;
 pub mod film;
+pub mod filter;
+pub mod integrator;
+pub mod light;
+pub mod material;
 pub mod ray;
+pub mod sampler;
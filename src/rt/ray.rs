@@ -1,13 +1,51 @@
+use crate::rt::{light::Light, material::Material};
 use crate::*;
+use std::sync::Arc;
 
 pub struct Ray {
     pub o: Vector<3>,
     pub d: Vector<3>,
+    pub time: Float,
+    pub differentials: Option<Differentials>,
+}
+
+// Rays offset by one pixel along the film's x and y axes.
+#[derive(Copy, Clone, Debug)]
+pub struct Differentials {
+    pub rx_o: Vector<3>,
+    pub rx_d: Vector<3>,
+    pub ry_o: Vector<3>,
+    pub ry_d: Vector<3>,
 }
 
 impl Ray {
     pub fn new(o: Vector<3>, d: Vector<3>) -> Ray {
-        Ray { o, d }
+        Ray {
+            o,
+            d,
+            time: 0.0,
+            differentials: None,
+        }
+    }
+
+    pub fn with_time(mut self, time: Float) -> Ray {
+        self.time = time;
+        self
+    }
+
+    pub fn with_differentials(mut self, differentials: Option<Differentials>) -> Ray {
+        self.differentials = differentials;
+        self
+    }
+
+    // Narrows the footprint when several samples are taken per pixel.
+    pub fn scale_differentials(&mut self, s: Float) {
+        if let Some(d) = self.differentials.as_mut() {
+            d.rx_o = self.o + (d.rx_o - self.o) * s;
+            d.ry_o = self.o + (d.ry_o - self.o) * s;
+            d.rx_d = self.d + (d.rx_d - self.d) * s;
+            d.ry_d = self.d + (d.ry_d - self.d) * s;
+        }
     }
 
     pub fn at(&self, t: Float) -> Vector<3> {
@@ -15,8 +53,282 @@ impl Ray {
     }
 }
 
+// Lives next to `Ray` so that `lm` does not depend on it.
+impl Bound<3> {
+    // Returns the part of `bound` during which the ray is inside the box.
+    pub fn intersect_ray(&self, ray: &Ray, bound: &Interval) -> Option<Interval> {
+        let inv_d = Vector3::new([ray.d[0].recip(), ray.d[1].recip(), ray.d[2].recip()]);
+        intersect_slabs(self, &ray.o, &inv_d, bound)
+    }
+}
+
+// Slab test with the reciprocal direction precomputed, so that traversals can
+// reuse it across boxes. The far distances are enlarged by their rounding error
+// so that the test never misses a box the ray grazes.
+pub fn intersect_slabs(
+    bounds: &Bound<3>,
+    o: &Vector<3>,
+    inv_d: &Vector<3>,
+    bound: &Interval,
+) -> Option<Interval> {
+    let (mut t_min, mut t_max) = bound[0];
+    for axis in 0..3 {
+        let (near, far) = if inv_d[axis] < 0.0 {
+            (bounds[axis].1, bounds[axis].0)
+        } else {
+            (bounds[axis].0, bounds[axis].1)
+        };
+        let t_near = (near - o[axis]) * inv_d[axis];
+        let t_far = (far - o[axis]) * inv_d[axis] * (1.0 + 2.0 * gamma(3));
+
+        // `max`/`min` discard the NaN produced by rays lying in a slab plane.
+        t_min = t_min.max(t_near);
+        t_max = t_max.min(t_far);
+        if t_min > t_max {
+            return None;
+        }
+    }
+
+    Some(Interval::new([(t_min, t_max)]))
+}
+
+// `normal` is the geometric normal and points out of the surface, `front_face`
+// tells whether the ray arrived from that side. `p_error` bounds the absolute
+// rounding error of `point` per component. The shading quantities start out
+// equal to the geometric ones. The screen-space derivatives are zero unless the
+// ray carried differentials. `light` is set when the surface emits.
+// `shape_id` is the index of the scene object that was hit and `primitive_id`
+// the index of the primitive within it: the triangle of a mesh, the element of a
+// BVH, or zero for shapes made of a single primitive.
 pub struct Intersection {
     pub distance: Float,
     pub point: Vector<3>,
+    pub p_error: Vector<3>,
+    pub normal: Vector<3>,
+    pub uv: Vector<2>,
+    pub dpdu: Vector<3>,
+    pub dpdv: Vector<3>,
+    pub shading: Shading,
+    pub front_face: bool,
+    pub shape_id: usize,
+    pub primitive_id: usize,
+    pub dpdx: Vector<3>,
+    pub dpdy: Vector<3>,
+    pub dudx: Float,
+    pub dvdx: Float,
+    pub dudy: Float,
+    pub dvdy: Float,
+    pub material: Option<Arc<dyn Material>>,
+    pub light: Option<Arc<dyn Light>>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Shading {
     pub normal: Vector<3>,
+    pub dpdu: Vector<3>,
+    pub dpdv: Vector<3>,
+}
+
+impl Intersection {
+    pub fn new(
+        ray: &Ray,
+        distance: Float,
+        point: Vector<3>,
+        normal: Vector<3>,
+        uv: Vector<2>,
+        dpdu: Vector<3>,
+        dpdv: Vector<3>,
+    ) -> Intersection {
+        let mut intersection = Intersection {
+            distance,
+            point,
+            p_error: Vector::<3>::zero(),
+            normal,
+            uv,
+            dpdu,
+            dpdv,
+            shading: Shading { normal, dpdu, dpdv },
+            front_face: ray.d.dot(&normal) < 0.0,
+            shape_id: 0,
+            primitive_id: 0,
+            dpdx: Vector::<3>::zero(),
+            dpdy: Vector::<3>::zero(),
+            dudx: 0.0,
+            dvdx: 0.0,
+            dudy: 0.0,
+            dvdy: 0.0,
+            material: None,
+            light: None,
+        };
+        intersection.compute_differentials(ray);
+        intersection
+    }
+
+    // The geometric normal is flipped into the hemisphere of the shading normal,
+    // which is the authoritative orientation once interpolated normals exist.
+    pub fn set_shading(&mut self, normal: Vector<3>, dpdu: Vector<3>, dpdv: Vector<3>) {
+        let normal = normal.normalize();
+        if self.normal.dot(&normal) < 0.0 {
+            self.normal = -self.normal;
+            self.front_face = !self.front_face;
+        }
+        self.shading = Shading { normal, dpdu, dpdv };
+    }
+
+    // Rays leaving the surface start just outside of the error bounds of the hit
+    // point so that they cannot intersect the surface again.
+    pub fn spawn_ray(&self, direction: Vector<3>) -> Ray {
+        let o = offset_ray_origin(&self.point, &self.p_error, &self.normal, &direction);
+        Ray::new(o, direction)
+    }
+
+    // The direction is left unnormalized so that `point` lies at distance one,
+    // shadow rays towards it should stop just short of that.
+    pub fn spawn_ray_to(&self, point: &Vector<3>) -> Ray {
+        let o = offset_ray_origin(
+            &self.point,
+            &self.p_error,
+            &self.normal,
+            &(point - &self.point),
+        );
+        Ray::new(o, *point - o)
+    }
+
+    pub fn shading_frame(&self) -> Frame {
+        Frame::from_xz(&self.shading.dpdu, &self.shading.normal)
+    }
+
+    // Intersects the offset rays with the tangent plane at the hit point and
+    // solves dp = dp/du du + dp/dv dv for the uv steps in the least squares
+    // sense.
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        let d = match &ray.differentials {
+            Some(d) => d,
+            None => return,
+        };
+        let n = &self.normal;
+        let (nx, ny) = (n.dot(&d.rx_d), n.dot(&d.ry_d));
+        if nx == 0.0 || ny == 0.0 {
+            return;
+        }
+
+        let plane = n.dot(&self.point);
+        let p_x = d.rx_o + d.rx_d * ((plane - n.dot(&d.rx_o)) / nx);
+        let p_y = d.ry_o + d.ry_d * ((plane - n.dot(&d.ry_o)) / ny);
+        self.dpdx = p_x - self.point;
+        self.dpdy = p_y - self.point;
+
+        let ata_00 = self.dpdu.dot(&self.dpdu);
+        let ata_01 = self.dpdu.dot(&self.dpdv);
+        let ata_11 = self.dpdv.dot(&self.dpdv);
+        let det_recip = sub_mul_pair(ata_00, ata_11, ata_01, ata_01).recip();
+        let det_recip = if det_recip.is_finite() {
+            det_recip
+        } else {
+            0.0
+        };
+
+        let atb_0x = self.dpdu.dot(&self.dpdx);
+        let atb_1x = self.dpdv.dot(&self.dpdx);
+        let atb_0y = self.dpdu.dot(&self.dpdy);
+        let atb_1y = self.dpdv.dot(&self.dpdy);
+        let clamp = |x: Float| {
+            if x.is_finite() {
+                x.clamp(-1E8, 1E8)
+            } else {
+                0.0
+            }
+        };
+        self.dudx = clamp(sub_mul_pair(ata_11, atb_0x, ata_01, atb_1x) * det_recip);
+        self.dvdx = clamp(sub_mul_pair(ata_00, atb_1x, ata_01, atb_0x) * det_recip);
+        self.dudy = clamp(sub_mul_pair(ata_11, atb_0y, ata_01, atb_1y) * det_recip);
+        self.dvdy = clamp(sub_mul_pair(ata_00, atb_1y, ata_01, atb_0y) * det_recip);
+    }
+}
+
+// Moves `p` along the normal past the box spanned by `p_error`, to the side `w`
+// points to, and rounds the result away from the surface.
+pub fn offset_ray_origin(
+    p: &Vector<3>,
+    p_error: &Vector<3>,
+    n: &Vector<3>,
+    w: &Vector<3>,
+) -> Vector<3> {
+    let d = n.abs().dot(p_error);
+    let offset = if w.dot(n) < 0.0 { -*n * d } else { *n * d };
+    let mut o = p + &offset;
+    for i in 0..3 {
+        if offset[i] > 0.0 {
+            o[i] = o[i].next_up();
+        } else if offset[i] < 0.0 {
+            o[i] = o[i].next_down();
+        }
+    }
+    o
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray() -> Ray {
+        let o = Vector3::new([0.3, 0.2, -5.0]);
+        let d = Vector3::new([0.0, 0.0, 1.0]);
+        Ray::new(o, d).with_differentials(Some(Differentials {
+            rx_o: o,
+            rx_d: Vector3::new([1E-4, 0.0, 1.0]),
+            ry_o: o,
+            ry_d: Vector3::new([0.0, 1E-4, 1.0]),
+        }))
+    }
+
+    fn assert_close(a: &Vector3, b: &Vector3, tolerance: Float) {
+        assert!((*a - *b).magnitude() < tolerance, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn shapes_report_differentials() {
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        let ray = ray();
+        let d = ray.differentials.unwrap();
+        let shapes: Vec<Box<dyn Shape>> = vec![
+            Box::new(Sphere::new(Vector3::zero(), 1.0)),
+            Box::new(Triangle::build([
+                Vector3::new([-2.0, -2.0, 0.5]),
+                Vector3::new([2.0, -2.0, 0.0]),
+                Vector3::new([0.0, 2.0, 0.0]),
+            ])),
+        ];
+
+        for shape in shapes {
+            let hit = shape.intersect(&ray, &bound).unwrap();
+            let hit_x = shape.intersect(&Ray::new(d.rx_o, d.rx_d), &bound).unwrap();
+            let hit_y = shape.intersect(&Ray::new(d.ry_o, d.ry_d), &bound).unwrap();
+            // The tangent plane matches the surface to first order.
+            assert_close(&hit.dpdx, &(hit_x.point - hit.point), 1E-7);
+            assert_close(&hit.dpdy, &(hit_y.point - hit.point), 1E-7);
+            let duv_x = Vector2::new([hit.dudx, hit.dvdx]);
+            let duv_y = Vector2::new([hit.dudy, hit.dvdy]);
+            assert!((duv_x - (hit_x.uv - hit.uv)).magnitude() < 1E-7);
+            assert!((duv_y - (hit_y.uv - hit.uv)).magnitude() < 1E-7);
+        }
+    }
+
+    #[test]
+    fn transformed_differentials_match_world_space() {
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        let center = Vector3::new([0.5, -0.25, 1.0]);
+        let world = Sphere::new(center, 2.0);
+        let scene = Scene::new().push(
+            Sphere::new(Vector3::zero(), 1.0),
+            &center,
+            &Vector3::new([0.0, 0.0, 30.0]),
+            &Vector3::new([2.0, 2.0, 2.0]),
+        );
+
+        let expected = world.intersect(&ray(), &bound).unwrap();
+        let hit = scene.intersect(&ray(), &bound).unwrap();
+        assert_close(&hit.dpdx, &expected.dpdx, 1E-12);
+        assert_close(&hit.dpdy, &expected.dpdy, 1E-12);
+    }
 }
@@ -0,0 +1,257 @@
+use crate::lm::{
+    primes::{
+        inverse_radical_inverse, owen_scrambled_radical_inverse, radical_inverse,
+        scrambled_radical_inverse, PRIME_TABLE_SIZE,
+    },
+    rng::{mix_bits, Rng},
+};
+use crate::*;
+
+const SAMPLE_STRIDE: i64 = 1 << 16;
+const MAX_HALTON_RESOLUTION: u64 = 128;
+
+// Samplers are positioned on a pixel sample first and then hand out consecutive
+// dimensions of that sample. The values only depend on the pixel sample, so
+// clones handed to different threads produce the same sequences.
+pub trait Sampler: Send + Sync {
+    fn samples_per_pixel(&self) -> usize;
+
+    fn clone_sampler(&self) -> Box<dyn Sampler>;
+
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize);
+
+    fn get_1d(&mut self) -> Float;
+
+    fn get_2d(&mut self) -> Vector2;
+
+    fn get_pixel_2d(&mut self) -> Vector2 {
+        self.get_2d()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IndependentSampler {
+    samples_per_pixel: usize,
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            samples_per_pixel,
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    fn clone_sampler(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        let stream = mix_bits(((pixel.0 as u64) << 32) ^ pixel.1 as u64);
+        self.rng.set_sequence(self.seed, stream);
+        self.rng.advance(index as i64 * SAMPLE_STRIDE);
+    }
+
+    fn get_1d(&mut self) -> Float {
+        self.rng.uniform_float()
+    }
+
+    fn get_2d(&mut self) -> Vector2 {
+        Vector2::new([self.rng.uniform_float(), self.rng.uniform_float()])
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Randomization {
+    None,
+    PermuteDigits,
+    Owen,
+}
+
+// The first two dimensions are scaled so that a 2^j x 3^k block of pixels is
+// covered by consecutive Halton points, which lets every pixel find its own
+// samples in the global sequence (Kollig and Keller).
+#[derive(Clone, Debug)]
+pub struct HaltonSampler {
+    samples_per_pixel: usize,
+    randomization: Randomization,
+    seed: u64,
+    base_scales: [u64; 2],
+    base_exponents: [u32; 2],
+    multiplicative_inverses: [u64; 2],
+    halton_index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(
+        samples_per_pixel: usize,
+        resolution: (usize, usize),
+        randomization: Randomization,
+        seed: u64,
+    ) -> HaltonSampler {
+        let resolution = [resolution.0 as u64, resolution.1 as u64];
+        let mut base_scales = [1; 2];
+        let mut base_exponents = [0; 2];
+        for (i, base) in [2, 3].into_iter().enumerate() {
+            while base_scales[i] < resolution[i].min(MAX_HALTON_RESOLUTION) {
+                base_scales[i] *= base;
+                base_exponents[i] += 1;
+            }
+        }
+
+        let multiplicative_inverses = [
+            multiplicative_inverse(base_scales[1], base_scales[0]),
+            multiplicative_inverse(base_scales[0], base_scales[1]),
+        ];
+
+        HaltonSampler {
+            samples_per_pixel,
+            randomization,
+            seed,
+            base_scales,
+            base_exponents,
+            multiplicative_inverses,
+            halton_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn sample_dimension(&self, dimension: usize) -> Float {
+        let hash = (mix_bits(1 + ((dimension as u64) << 4)) ^ self.seed) as u32;
+        match self.randomization {
+            Randomization::None => radical_inverse(dimension, self.halton_index),
+            Randomization::PermuteDigits => {
+                scrambled_radical_inverse(dimension, self.halton_index, hash)
+            }
+            Randomization::Owen => {
+                owen_scrambled_radical_inverse(dimension, self.halton_index, hash)
+            }
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn samples_per_pixel(&self) -> usize {
+        self.samples_per_pixel
+    }
+
+    fn clone_sampler(&self) -> Box<dyn Sampler> {
+        Box::new(self.clone())
+    }
+
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.halton_index = 0;
+        let stride = self.base_scales[0] * self.base_scales[1];
+        if stride > 1 {
+            let p = [
+                pixel.0 as u64 % MAX_HALTON_RESOLUTION,
+                pixel.1 as u64 % MAX_HALTON_RESOLUTION,
+            ];
+            for (i, base) in [2, 3].into_iter().enumerate() {
+                let offset = inverse_radical_inverse(p[i], base, self.base_exponents[i]);
+                self.halton_index += offset
+                    * (stride / self.base_scales[i])
+                    * self.multiplicative_inverses[i];
+            }
+            self.halton_index %= stride;
+        }
+
+        self.halton_index += index as u64 * stride;
+        self.dimension = 2;
+    }
+
+    fn get_1d(&mut self) -> Float {
+        if self.dimension >= PRIME_TABLE_SIZE {
+            self.dimension = 2;
+        }
+        self.dimension += 1;
+        self.sample_dimension(self.dimension - 1)
+    }
+
+    fn get_2d(&mut self) -> Vector2 {
+        if self.dimension + 1 >= PRIME_TABLE_SIZE {
+            self.dimension = 2;
+        }
+        self.dimension += 2;
+        Vector2::new([
+            self.sample_dimension(self.dimension - 2),
+            self.sample_dimension(self.dimension - 1),
+        ])
+    }
+
+    fn get_pixel_2d(&mut self) -> Vector2 {
+        Vector2::new([
+            radical_inverse(0, self.halton_index >> self.base_exponents[0]),
+            radical_inverse(1, self.halton_index / self.base_scales[1]),
+        ])
+    }
+}
+
+fn multiplicative_inverse(a: u64, n: u64) -> u64 {
+    let (x, _) = extended_gcd(a, n);
+    x.rem_euclid(n as i64) as u64
+}
+
+fn extended_gcd(a: u64, b: u64) -> (i64, i64) {
+    if b == 0 {
+        return (1, 0);
+    }
+    let d = (a / b) as i64;
+    let (x, y) = extended_gcd(b, a % b);
+    (y, x - d * y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halton_samples_land_in_their_pixel() {
+        let mut sampler = HaltonSampler::new(16, (40, 30), Randomization::Owen, 3);
+        assert_eq!(sampler.base_scales, [64, 81]);
+
+        for pixel in [(0, 0), (5, 7), (39, 29)] {
+            for index in 0..16 {
+                sampler.start_pixel_sample(pixel, index);
+                let h = sampler.halton_index;
+                let x = radical_inverse(0, h) * sampler.base_scales[0] as Float;
+                let y = radical_inverse(1, h) * sampler.base_scales[1] as Float;
+                assert_eq!((x as usize, y as usize), pixel);
+
+                let offset = sampler.get_pixel_2d();
+                assert!((offset[0] - x.fract()).abs() < 1E-9);
+                assert!((offset[1] - y.fract()).abs() < 1E-9);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_only_depend_on_the_pixel_sample() {
+        for randomization in [
+            Randomization::None,
+            Randomization::PermuteDigits,
+            Randomization::Owen,
+        ] {
+            let mut a = HaltonSampler::new(4, (8, 8), randomization, 9);
+            let mut b = a.clone();
+            a.start_pixel_sample((1, 2), 0);
+            a.get_2d();
+            a.start_pixel_sample((3, 4), 2);
+            b.start_pixel_sample((3, 4), 2);
+            for _ in 0..10 {
+                assert_eq!(a.get_1d(), b.get_1d());
+                assert_eq!(a.get_2d().v, b.get_2d().v);
+            }
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use crate::rt::ray::intersect_slabs;
+use crate::*;
+
+const BUCKETS: usize = 12;
+const MAX_PRIMITIVES_IN_LEAF: usize = 4;
+
+// Interior nodes store the index of their second child in `offset`, the first
+// child always directly follows its parent. Leaves store their first position in
+// `indices`.
+#[derive(Copy, Clone, Debug)]
+struct BvhNode {
+    bounds: Bound<3>,
+    offset: usize,
+    count: usize,
+    axis: usize,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct PrimitiveInfo {
+    index: u32,
+    bounds: Bound<3>,
+    centroid: Vector3,
+}
+
+// Node hierarchy over primitives known only by their index, so callers can keep
+// the primitives in whatever storage suits them. Primitives without finite
+// bounds are kept after the ones the nodes reference and always visited.
+#[derive(Clone, Debug, Default)]
+pub struct BvhTree {
+    nodes: Vec<BvhNode>,
+    indices: Vec<u32>,
+    bounded: usize,
+}
+
+impl BvhTree {
+    pub fn new(bounds: impl IntoIterator<Item = Bound<3>>) -> BvhTree {
+        let mut infos = Vec::new();
+        let mut unbounded = Vec::new();
+        for (index, bounds) in bounds.into_iter().enumerate() {
+            let index = u32::try_from(index).expect("Too many primitives for a BVH.");
+            if bounds.is_finite() {
+                let centroid = bounds.centroid();
+                infos.push(PrimitiveInfo {
+                    index,
+                    bounds,
+                    centroid,
+                });
+            } else {
+                unbounded.push(index);
+            }
+        }
+
+        let bounded = infos.len();
+        let mut nodes = Vec::with_capacity(2 * bounded);
+        let mut indices = Vec::with_capacity(bounded + unbounded.len());
+        if bounded > 0 {
+            Self::build(&mut nodes, &mut infos, &mut indices);
+        }
+        indices.extend(unbounded);
+
+        BvhTree {
+            nodes,
+            indices,
+            bounded,
+        }
+    }
+
+    // Bounds of the primitives the nodes reference, the unbounded ones excluded.
+    pub fn bounds(&self) -> Bound<3> {
+        match self.nodes.first() {
+            Some(root) => root.bounds,
+            None => Bound::<3>::empty(),
+        }
+    }
+
+    pub fn unbounded(&self) -> &[u32] {
+        &self.indices[self.bounded..]
+    }
+
+    fn build(
+        nodes: &mut Vec<BvhNode>,
+        infos: &mut [PrimitiveInfo],
+        indices: &mut Vec<u32>,
+    ) -> usize {
+        let index = nodes.len();
+        let bounds = infos
+            .iter()
+            .fold(Bound::<3>::empty(), |b, p| b.union(&p.bounds));
+        let centroid_bounds = infos
+            .iter()
+            .fold(Bound::<3>::empty(), |b, p| b.union_point(&p.centroid));
+        let axis = centroid_bounds.max_extent();
+
+        let leaf = BvhNode {
+            bounds,
+            offset: indices.len(),
+            count: infos.len(),
+            axis,
+        };
+        nodes.push(leaf);
+
+        if infos.len() == 1 || centroid_bounds[axis].0 == centroid_bounds[axis].1 {
+            indices.extend(infos.iter().map(|p| p.index));
+            return index;
+        }
+
+        let mid = match Self::split(infos, &bounds, &centroid_bounds, axis) {
+            Some(mid) => mid,
+            None => {
+                indices.extend(infos.iter().map(|p| p.index));
+                return index;
+            }
+        };
+
+        let (left, right) = infos.split_at_mut(mid);
+        Self::build(nodes, left, indices);
+        let second = Self::build(nodes, right, indices);
+        nodes[index] = BvhNode {
+            bounds,
+            offset: second,
+            count: 0,
+            axis,
+        };
+
+        index
+    }
+
+    // Partitions `infos` along `axis` with the surface area heuristic and returns
+    // the split position, or `None` when a leaf is cheaper than any split.
+    fn split(
+        infos: &mut [PrimitiveInfo],
+        bounds: &Bound<3>,
+        centroid_bounds: &Bound<3>,
+        axis: usize,
+    ) -> Option<usize> {
+        if infos.len() <= 2 {
+            let mid = infos.len() / 2;
+            infos.select_nth_unstable_by(mid, |a, b| {
+                a.centroid[axis].total_cmp(&b.centroid[axis])
+            });
+            return Some(mid);
+        }
+
+        let (min, max) = centroid_bounds[axis];
+        let bucket_of = |p: &PrimitiveInfo| {
+            let offset = (p.centroid[axis] - min) / (max - min);
+            ((offset * BUCKETS as Float) as usize).min(BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; BUCKETS];
+        let mut buckets = [Bound::<3>::empty(); BUCKETS];
+        for p in infos.iter() {
+            let b = bucket_of(p);
+            counts[b] += 1;
+            buckets[b] = buckets[b].union(&p.bounds);
+        }
+
+        let area = bounds.surface_area();
+        let mut costs = [0.0; BUCKETS - 1];
+        for (i, cost) in costs.iter_mut().enumerate() {
+            let (mut b0, mut b1) = (Bound::<3>::empty(), Bound::<3>::empty());
+            let (mut c0, mut c1) = (0, 0);
+            for j in 0..=i {
+                b0 = b0.union(&buckets[j]);
+                c0 += counts[j];
+            }
+            for j in i + 1..BUCKETS {
+                b1 = b1.union(&buckets[j]);
+                c1 += counts[j];
+            }
+            let weighted =
+                c0 as Float * b0.surface_area() + c1 as Float * b1.surface_area();
+            *cost = if area > 0.0 {
+                0.125 + weighted / area
+            } else {
+                0.125 + (c0 + c1) as Float
+            };
+        }
+
+        let mut bucket = 0;
+        for (i, cost) in costs.iter().enumerate() {
+            if *cost < costs[bucket] {
+                bucket = i;
+            }
+        }
+        let cost = costs[bucket];
+
+        if infos.len() <= MAX_PRIMITIVES_IN_LEAF && cost >= infos.len() as Float {
+            return None;
+        }
+
+        let mut mid = 0;
+        for i in 0..infos.len() {
+            if bucket_of(&infos[i]) <= bucket {
+                infos.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        if mid == 0 || mid == infos.len() {
+            mid = infos.len() / 2;
+            infos.select_nth_unstable_by(mid, |a, b| {
+                a.centroid[axis].total_cmp(&b.centroid[axis])
+            });
+        }
+
+        Some(mid)
+    }
+
+    // Visits the indices of the primitives whose bounds the ray reaches, nearest
+    // nodes first, followed by the unbounded ones. `visit` may shrink the interval
+    // and returns true to stop the traversal.
+    pub fn traverse(
+        &self,
+        ray: &Ray,
+        bound: &Interval,
+        mut visit: impl FnMut(u32, &mut Interval) -> bool,
+    ) {
+        let mut b = *bound;
+
+        if !self.nodes.is_empty() {
+            let inv_d =
+                Vector3::new([ray.d[0].recip(), ray.d[1].recip(), ray.d[2].recip()]);
+            let is_neg = [inv_d[0] < 0.0, inv_d[1] < 0.0, inv_d[2] < 0.0];
+            let mut stack = Vec::with_capacity(64);
+            let mut current = 0;
+
+            loop {
+                let node = &self.nodes[current];
+                if intersect_slabs(&node.bounds, &ray.o, &inv_d, &b).is_some() {
+                    if node.count > 0 {
+                        for &i in &self.indices[node.offset..node.offset + node.count] {
+                            if visit(i, &mut b) {
+                                return;
+                            }
+                        }
+                    } else if is_neg[node.axis] {
+                        stack.push(current + 1);
+                        current = node.offset;
+                        continue;
+                    } else {
+                        stack.push(node.offset);
+                        current += 1;
+                        continue;
+                    }
+                }
+
+                match stack.pop() {
+                    Some(next) => current = next,
+                    None => break,
+                }
+            }
+        }
+
+        for &i in self.unbounded() {
+            if visit(i, &mut b) {
+                return;
+            }
+        }
+    }
+}
+
+pub struct Bvh<T: Shape> {
+    primitives: Vec<T>,
+    tree: BvhTree,
+}
+
+impl<T: Shape> Bvh<T> {
+    pub fn new(primitives: Vec<T>) -> Bvh<T> {
+        let tree = BvhTree::new(primitives.iter().map(|p| p.bounds()));
+        Bvh { primitives, tree }
+    }
+
+    pub fn primitives(&self) -> &[T] {
+        &self.primitives
+    }
+}
+
+impl<T: Shape> Shape for Bvh<T> {
+    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
+        let mut intersection = None;
+        self.tree.traverse(ray, bound, |i, b| {
+            if let Some(mut candidate) = self.primitives[i as usize].intersect(ray, b) {
+                b[0].1 = candidate.distance;
+                candidate.primitive_id = i as usize;
+                intersection = Some(candidate);
+            }
+            false
+        });
+        intersection
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        let mut hit = false;
+        self.tree.traverse(ray, bound, |i, b| {
+            hit = self.primitives[i as usize].intersect_p(ray, b);
+            hit
+        });
+        hit
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        self.tree
+            .unbounded()
+            .iter()
+            .fold(self.tree.bounds(), |b, &i| {
+                b.union(&self.primitives[i as usize].bounds())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lm::rng::Rng;
+
+    fn spheres(rng: &mut Rng, count: usize) -> Vec<Sphere> {
+        (0..count)
+            .map(|_| {
+                let center = Vector3::new([
+                    rng.uniform_float() * 20.0 - 10.0,
+                    rng.uniform_float() * 20.0 - 10.0,
+                    rng.uniform_float() * 20.0 - 10.0,
+                ]);
+                Sphere::new(center, 0.1 + rng.uniform_float())
+            })
+            .collect()
+    }
+
+    fn random_ray(rng: &mut Rng) -> Ray {
+        let o = Vector3::new([
+            rng.uniform_float() * 30.0 - 15.0,
+            rng.uniform_float() * 30.0 - 15.0,
+            rng.uniform_float() * 30.0 - 15.0,
+        ]);
+        let target = Vector3::new([
+            rng.uniform_float() * 10.0 - 5.0,
+            rng.uniform_float() * 10.0 - 5.0,
+            rng.uniform_float() * 10.0 - 5.0,
+        ]);
+        Ray::new(o, target - o)
+    }
+
+    #[test]
+    fn matches_linear_search() {
+        let mut rng = Rng::new(7);
+        let bvh = Bvh::new(spheres(&mut rng, 200));
+        let linear = spheres(&mut Rng::new(7), 200);
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+
+        for _ in 0..2000 {
+            let ray = random_ray(&mut rng);
+            let expected = linear
+                .iter()
+                .enumerate()
+                .filter_map(|(i, s)| s.intersect(&ray, &bound).map(|h| (h.distance, i)))
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+            let found = bvh
+                .intersect(&ray, &bound)
+                .map(|i| (i.distance, i.primitive_id));
+            assert_eq!(found, expected);
+            assert_eq!(bvh.intersect_p(&ray, &bound), expected.is_some());
+        }
+    }
+
+    #[test]
+    fn respects_interval() {
+        let bvh = Bvh::new(vec![
+            Sphere::new(Vector3::new([0.0, 0.0, 5.0]), 1.0),
+            Sphere::new(Vector3::new([0.0, 0.0, 10.0]), 1.0),
+        ]);
+        let ray = Ray::new(Vector3::zero(), Vector3::new([0.0, 0.0, 1.0]));
+
+        let near = bvh.intersect(&ray, &Interval::new([(0.0, Float::INFINITY)]));
+        assert_eq!(near.map(|i| i.distance), Some(4.0));
+        let far = bvh.intersect(&ray, &Interval::new([(7.0, Float::INFINITY)]));
+        assert_eq!(far.map(|i| i.distance), Some(9.0));
+        assert!(!bvh.intersect_p(&ray, &Interval::new([(0.0, 3.0)])));
+    }
+
+    #[test]
+    fn bounds_cover_primitives() {
+        let bvh = Bvh::new(spheres(&mut Rng::new(3), 50));
+        let bounds = bvh.bounds();
+        for sphere in bvh.primitives() {
+            assert_eq!(bounds.union(&sphere.bounds()).b, bounds.b);
+        }
+        assert!(Bvh::<Sphere>::new(Vec::new()).bounds().is_empty());
+    }
+}
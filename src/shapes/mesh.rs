@@ -1,89 +1,457 @@
+use crate::lm::sampling::Distribution1D;
+use crate::shapes::bvh::BvhTree;
+use crate::shapes::shape::ShapeSample;
+use crate::shapes::triangle::{
+    intersect_triangle, sample_triangle, triangle_area, triangle_intersection,
+    DEFAULT_UVS,
+};
+use crate::utils::parser::{read_obj, ObjError, ObjVertex, Triangulation};
 use crate::*;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 
+// Normals and uvs are either empty or hold one entry per position.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<Vector3>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<Vector2>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn vertex_indices(&self, triangle: u32) -> [usize; 3] {
+        let i = 3 * triangle as usize;
+        [
+            self.indices[i] as usize,
+            self.indices[i + 1] as usize,
+            self.indices[i + 2] as usize,
+        ]
+    }
+
+    pub fn vertices(&self, triangle: u32) -> [Vector3; 3] {
+        self.vertex_indices(triangle).map(|i| self.positions[i])
+    }
+
+    pub fn normals(&self, triangle: u32) -> Option<[Vector3; 3]> {
+        if self.normals.is_empty() {
+            None
+        } else {
+            Some(self.vertex_indices(triangle).map(|i| self.normals[i]))
+        }
+    }
+
+    pub fn uvs(&self, triangle: u32) -> [Vector2; 3] {
+        if self.uvs.is_empty() {
+            DEFAULT_UVS
+        } else {
+            self.vertex_indices(triangle).map(|i| self.uvs[i])
+        }
+    }
+
+    fn intersect(
+        &self,
+        triangle: u32,
+        ray: &Ray,
+        bound: &Interval,
+    ) -> Option<Intersection> {
+        let vertices = self.vertices(triangle);
+        let hit = intersect_triangle(ray, bound, &vertices)?;
+        let normals = self.normals(triangle);
+        let mut intersection = triangle_intersection(
+            ray,
+            hit,
+            &vertices,
+            normals.as_ref(),
+            &self.uvs(triangle),
+        );
+        intersection.primitive_id = triangle as usize;
+        Some(intersection)
+    }
+}
+
+// A triangle of a mesh, which shares the mesh's vertex data instead of copying
+// it.
+#[derive(Clone, Debug)]
+pub struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    index: u32,
+}
+
+impl MeshTriangle {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn vertices(&self) -> [Vector3; 3] {
+        self.mesh.vertices(self.index)
+    }
+
+    pub fn area(&self) -> Float {
+        triangle_area(&self.vertices())
+    }
+}
+
+impl Shape for MeshTriangle {
+    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
+        self.mesh.intersect(self.index, ray, bound)
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        intersect_triangle(ray, bound, &self.vertices()).is_some()
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        self.vertices()
+            .iter()
+            .fold(Bound::<3>::empty(), |b, v| b.union_point(v))
+    }
+
+    fn sample(&self, u: &Vector2) -> Option<ShapeSample> {
+        let normals = self.mesh.normals(self.index);
+        sample_triangle(&self.vertices(), normals.as_ref(), u)
+    }
+
+    fn pdf(&self, _point: &Vector3) -> Float {
+        self.area().recip()
+    }
+}
+
+#[derive(Debug)]
+pub enum MeshError {
+    Obj(ObjError),
+    TooManyVertices(usize),
+    TooManyTriangles(usize),
+    IndexOutOfRange(u32),
+    IndexCount(usize),
+    NormalCount(usize),
+    UvCount(usize),
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Obj(e) => write!(f, "{e}"),
+            MeshError::TooManyVertices(n) => {
+                write!(f, "{n} vertices do not fit in 32 bit indices")
+            }
+            MeshError::TooManyTriangles(n) => {
+                write!(f, "{n} triangles do not fit in 32 bit indices")
+            }
+            MeshError::IndexOutOfRange(i) => {
+                write!(f, "vertex index {i} is out of range")
+            }
+            MeshError::IndexCount(n) => {
+                write!(f, "{n} vertex indices do not form whole triangles")
+            }
+            MeshError::NormalCount(n) => {
+                write!(f, "{n} normals do not match the number of positions")
+            }
+            MeshError::UvCount(n) => {
+                write!(f, "{n} uvs do not match the number of positions")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MeshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MeshError::Obj(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ObjError> for MeshError {
+    fn from(error: ObjError) -> MeshError {
+        MeshError::Obj(error)
+    }
+}
+
+// Triangles are picked proportionally to their area when sampling, so points are
+// uniform over the whole surface. The BVH only holds triangle indices into `data`.
 pub struct Mesh {
-    pub triangles: Vec<Triangle>,
+    data: Arc<MeshData>,
+    triangles: BvhTree,
+    areas: Distribution1D,
+    area: Float,
 }
 
 impl Mesh {
-    pub fn new(triangles: Vec<Triangle>) -> Mesh {
-        Mesh { triangles }
-    }
-
-    pub fn from_obj(path: &str) -> Mesh {
-        let mut vs: Vec<Vector3> = Vec::new();
-        let mut ns: Vec<Vector3> = Vec::new();
-        let mut triangles = Vec::<Triangle>::new();
-
-        let file = File::open(path).expect("Cannot open file.");
-        for line in BufReader::new(file).lines() {
-            let ln = line.expect("Cannot read file.");
-            if let Some(s) = ln.split_once(' ') {
-                match s.0 {
-                    "v" => {
-                        let v: Vec<&str> = s.1.split(' ').collect();
-                        let vertice = Vector3::new([
-                            v[0].parse::<Float>().unwrap(),
-                            v[1].parse::<Float>().unwrap(),
-                            v[2].parse::<Float>().unwrap(),
-                        ]);
-                        vs.push(vertice);
-                    }
-                    "vn" => {
-                        let n = s.1.split(' ').collect::<Vec<&str>>();
-                        let normal = Vector3::new([
-                            n[0].parse::<Float>().unwrap(),
-                            n[1].parse::<Float>().unwrap(),
-                            n[2].parse::<Float>().unwrap(),
-                        ]);
-                        ns.push(normal);
+    pub fn new(data: MeshData) -> Result<Mesh, MeshError> {
+        if !data.indices.len().is_multiple_of(3) {
+            return Err(MeshError::IndexCount(data.indices.len()));
+        }
+        if !data.normals.is_empty() && data.normals.len() != data.positions.len() {
+            return Err(MeshError::NormalCount(data.normals.len()));
+        }
+        if !data.uvs.is_empty() && data.uvs.len() != data.positions.len() {
+            return Err(MeshError::UvCount(data.uvs.len()));
+        }
+        let count = u32::try_from(data.triangle_count())
+            .map_err(|_| MeshError::TooManyTriangles(data.triangle_count()))?;
+        if let Some(&i) = data
+            .indices
+            .iter()
+            .find(|&&i| i as usize >= data.positions.len())
+        {
+            return Err(MeshError::IndexOutOfRange(i));
+        }
+
+        let triangles = BvhTree::new((0..count).map(|i| {
+            data.vertices(i)
+                .iter()
+                .fold(Bound::<3>::empty(), |b, v| b.union_point(v))
+        }));
+        let areas = (0..count)
+            .map(|i| triangle_area(&data.vertices(i)))
+            .collect::<Vec<Float>>();
+        let area = areas.iter().sum();
+
+        Ok(Mesh {
+            data: Arc::new(data),
+            triangles,
+            areas: Distribution1D::new(&areas),
+            area,
+        })
+    }
+
+    pub fn from_triangles(triangles: &[Triangle]) -> Result<Mesh, MeshError> {
+        let vertices = 3 * triangles.len();
+        u32::try_from(vertices).map_err(|_| MeshError::TooManyVertices(vertices))?;
+
+        let mut data = MeshData::default();
+        for t in triangles {
+            let i = data.positions.len() as u32;
+            data.positions.extend(t.vertices);
+            data.normals.extend(t.normals);
+            data.indices.extend([i, i + 1, i + 2]);
+        }
+
+        Mesh::new(data)
+    }
+
+    pub fn from_obj(path: &str) -> Result<Mesh, MeshError> {
+        let model = read_obj(path, Triangulation::EarClip)?;
+        let corners = || model.triangles.iter().flatten();
+        let has_normals = corners().all(|v| v.normal.is_some());
+        let has_uvs = corners().all(|v| v.uv.is_some());
+
+        // OBJ indexes every attribute separately, so each distinct combination
+        // of references becomes one shared vertex.
+        let mut data = MeshData::default();
+        let mut vertices = HashMap::<ObjVertex, u32>::new();
+        for v in corners() {
+            let index = match vertices.get(v) {
+                Some(&index) => index,
+                None => {
+                    let index = u32::try_from(data.positions.len()).map_err(|_| {
+                        MeshError::TooManyVertices(data.positions.len() + 1)
+                    })?;
+                    data.positions.push(model.positions[v.position]);
+                    if let (true, Some(n)) = (has_normals, v.normal) {
+                        data.normals.push(model.normals[n]);
                     }
-                    "f" => {
-                        let p = s.1.replacen("//", " ", 3);
-                        let i: Vec<&str> = p.split(' ').collect();
-                        let v = (
-                            i[0].parse::<usize>().unwrap(),
-                            i[2].parse::<usize>().unwrap(),
-                            i[4].parse::<usize>().unwrap(),
-                        );
-                        let vn = (
-                            i[1].parse::<usize>().unwrap(),
-                            i[3].parse::<usize>().unwrap(),
-                            i[5].parse::<usize>().unwrap(),
-                        );
-                        let vertices = [
-                            vs[v.0.checked_sub(1).unwrap_or(0)],
-                            vs[v.1.checked_sub(1).unwrap_or(0)],
-                            vs[v.2.checked_sub(1).unwrap_or(0)],
-                        ];
-                        let normals = [
-                            ns[vn.0.checked_sub(1).unwrap_or(0)],
-                            ns[vn.1.checked_sub(1).unwrap_or(0)],
-                            ns[vn.2.checked_sub(1).unwrap_or(0)],
-                        ];
-                        triangles.push(Triangle::new(vertices, normals));
+                    if let (true, Some(t)) = (has_uvs, v.uv) {
+                        data.uvs.push(model.uvs[t]);
                     }
-                    _ => (),
+                    vertices.insert(*v, index);
+                    index
                 }
-            }
+            };
+            data.indices.push(index);
+        }
+
+        Mesh::new(data)
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.data
+    }
+
+    pub fn area(&self) -> Float {
+        self.area
+    }
+
+    pub fn triangle(&self, index: u32) -> MeshTriangle {
+        MeshTriangle {
+            mesh: self.data.clone(),
+            index,
         }
+    }
 
-        Mesh { triangles }
+    pub fn triangles(&self) -> impl Iterator<Item = MeshTriangle> + '_ {
+        (0..self.data.triangle_count() as u32).map(|i| self.triangle(i))
     }
 }
 
 impl Shape for Mesh {
     fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
         let mut intersection = None;
-        let mut b = bound.clone();
-        for t in self.triangles.iter() {
-            if let Some(candidate) = t.intersect(ray, &b) {
+        self.triangles.traverse(ray, bound, |i, b| {
+            if let Some(candidate) = self.data.intersect(i, ray, b) {
                 b[0].1 = candidate.distance;
                 intersection = Some(candidate);
             }
+            false
+        });
+        intersection
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        let mut hit = false;
+        self.triangles.traverse(ray, bound, |i, b| {
+            hit = intersect_triangle(ray, b, &self.data.vertices(i)).is_some();
+            hit
+        });
+        hit
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        self.triangles.bounds()
+    }
+
+    fn sample(&self, u: &Vector2) -> Option<ShapeSample> {
+        if self.area == 0.0 {
+            return None;
         }
+        let (index, _, u_0) = self.areas.sample_discrete(u[0]);
+        let index = index as u32;
+        let normals = self.data.normals(index);
+        let mut sample = sample_triangle(
+            &self.data.vertices(index),
+            normals.as_ref(),
+            &Vector2::new([u_0, u[1]]),
+        )?;
+        sample.pdf = self.area.recip();
+        Some(sample)
+    }
 
-        intersection
+    fn pdf(&self, _point: &Vector3) -> Float {
+        if self.area == 0.0 {
+            0.0
+        } else {
+            self.area.recip()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lm::rng::Rng;
+
+    #[test]
+    fn rejects_invalid_indices() {
+        let data = MeshData {
+            positions: vec![Vector3::zero(); 3],
+            indices: vec![0, 1, 3],
+            ..MeshData::default()
+        };
+        assert!(matches!(
+            Mesh::new(data),
+            Err(MeshError::IndexOutOfRange(3))
+        ));
+    }
+
+    #[test]
+    fn rejects_mismatched_attributes() {
+        let data = MeshData {
+            positions: vec![Vector3::zero(); 4],
+            indices: vec![0, 1, 2, 3],
+            ..MeshData::default()
+        };
+        assert!(matches!(Mesh::new(data), Err(MeshError::IndexCount(4))));
+
+        let data = MeshData {
+            positions: vec![Vector3::zero(); 3],
+            normals: vec![Vector3::zero(); 2],
+            indices: vec![0, 1, 2],
+            ..MeshData::default()
+        };
+        assert!(matches!(Mesh::new(data), Err(MeshError::NormalCount(2))));
+
+        let data = MeshData {
+            positions: vec![Vector3::zero(); 3],
+            uvs: vec![Vector2::zero(); 4],
+            indices: vec![0, 1, 2],
+            ..MeshData::default()
+        };
+        assert!(matches!(Mesh::new(data), Err(MeshError::UvCount(4))));
+    }
+
+    #[test]
+    fn triangle_handles_share_the_mesh() {
+        let triangles = [
+            Triangle::build([
+                Vector3::new([0.0, 0.0, 0.0]),
+                Vector3::new([1.0, 0.0, 0.0]),
+                Vector3::new([0.0, 1.0, 0.0]),
+            ]),
+            Triangle::build([
+                Vector3::new([0.0, 0.0, 1.0]),
+                Vector3::new([1.0, 0.0, 1.0]),
+                Vector3::new([0.0, 1.0, 1.0]),
+            ]),
+        ];
+        let mesh = Mesh::from_triangles(&triangles).unwrap();
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        let ray = Ray::new(
+            Vector3::new([0.25, 0.25, 2.0]),
+            Vector3::new([0.0, 0.0, -1.0]),
+        );
+
+        let handles = mesh.triangles().collect::<Vec<MeshTriangle>>();
+        assert_eq!(handles.len(), 2);
+        for (handle, triangle) in handles.iter().zip(&triangles) {
+            assert_eq!(handle.bounds().b, triangle.bounds().b);
+            assert_eq!(handle.area(), triangle.area());
+            let hit = handle.intersect(&ray, &bound).unwrap();
+            assert_eq!(
+                hit.distance,
+                triangle.intersect(&ray, &bound).unwrap().distance
+            );
+            assert_eq!(hit.primitive_id, handle.index() as usize);
+            assert!(handle.intersect_p(&ray, &bound));
+        }
+        assert_eq!(mesh.intersect(&ray, &bound).unwrap().primitive_id, 1);
+    }
+
+    #[test]
+    fn matches_its_triangles() {
+        let mut rng = Rng::new(11);
+        let mut point = || {
+            Vector3::new([
+                rng.uniform_float() * 4.0 - 2.0,
+                rng.uniform_float() * 4.0 - 2.0,
+                rng.uniform_float() * 4.0 - 2.0,
+            ])
+        };
+        let triangles = (0..64)
+            .map(|_| Triangle::build([point(), point(), point()]))
+            .collect::<Vec<Triangle>>();
+        let mesh = Mesh::from_triangles(&triangles).unwrap();
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+
+        for _ in 0..1000 {
+            let o = point() * 3.0;
+            let ray = Ray::new(o, point() - o);
+            let expected = triangles
+                .iter()
+                .enumerate()
+                .filter_map(|(i, t)| t.intersect(&ray, &bound).map(|h| (h.distance, i)))
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+            let found = mesh
+                .intersect(&ray, &bound)
+                .map(|h| (h.distance, h.primitive_id));
+            assert_eq!(found, expected);
+            assert_eq!(mesh.intersect_p(&ray, &bound), expected.is_some());
+        }
     }
 }
@@ -1,5 +1,8 @@
+pub mod bvh;
 pub mod mesh;
 pub mod plane;
+pub mod primitive;
+pub mod quad;
 pub mod scene;
 pub mod shape;
 pub mod sphere;
@@ -1,3 +1,4 @@
+use crate::shapes::shape::{accept_distance, plane_distance};
 use crate::*;
 
 pub struct Plane {
@@ -10,21 +11,39 @@ impl Plane {
         let normal = normal.normalize();
         Plane { point, normal }
     }
+
+    fn distance(&self, ray: &Ray, bound: &Interval) -> Option<Float> {
+        // Rays parallel to the plane never reach it.
+        let (distance, error) = plane_distance(&self.point, &self.normal, ray);
+        accept_distance(bound, distance, error).then_some(distance)
+    }
 }
 
 impl Shape for Plane {
     fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
-        let distance = (self.point - ray.o).dot(&self.normal) / ray.d.dot(&self.normal);
-        if !bound.contains(&Vector1::new([distance])) {
-            return None;
-        }
-
-        let point = ray.at(distance);
-        let normal = self.normal;
-        Some(Intersection {
-            distance,
-            point,
-            normal,
-        })
+        let distance = self.distance(ray, bound)?;
+
+        // The plane is parameterised by distance along a frame around the normal.
+        // The hit is projected back onto the plane, which leaves only the error of
+        // the projection itself.
+        let (dpdu, dpdv) = self.normal.coordinate_system();
+        let offset = ray.at(distance) - self.point;
+        let uv = Vector2::new([offset.dot(&dpdu), offset.dot(&dpdv)]);
+        let point = self.point + dpdu * uv[0] + dpdv * uv[1];
+        let p_error =
+            (self.point.abs() + (dpdu * uv[0]).abs() + (dpdv * uv[1]).abs()) * gamma(4);
+
+        let mut intersection =
+            Intersection::new(ray, distance, point, self.normal, uv, dpdu, dpdv);
+        intersection.p_error = p_error;
+        Some(intersection)
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        self.distance(ray, bound).is_some()
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        Bound::<3>::all()
     }
 }
@@ -0,0 +1,59 @@
+use crate::rt::{light::Light, material::Material};
+use crate::shapes::shape::ShapeSample;
+use crate::*;
+use std::sync::Arc;
+
+// A shape with its material and, for emitters, the area light it belongs to.
+pub struct Primitive {
+    pub shape: Box<dyn Shape>,
+    pub material: Arc<dyn Material>,
+    pub light: Option<Arc<dyn Light>>,
+}
+
+impl Primitive {
+    pub fn new(shape: impl Shape + 'static, material: Arc<dyn Material>) -> Primitive {
+        Primitive {
+            shape: Box::new(shape),
+            material,
+            light: None,
+        }
+    }
+
+    pub fn with_light(mut self, light: Arc<dyn Light>) -> Primitive {
+        self.light = Some(light);
+        self
+    }
+}
+
+impl Shape for Primitive {
+    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
+        let mut intersection = self.shape.intersect(ray, bound)?;
+        intersection.material = Some(self.material.clone());
+        intersection.light = self.light.clone();
+        Some(intersection)
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        self.shape.intersect_p(ray, bound)
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        self.shape.bounds()
+    }
+
+    fn sample(&self, u: &Vector2) -> Option<ShapeSample> {
+        self.shape.sample(u)
+    }
+
+    fn pdf(&self, point: &Vector3) -> Float {
+        self.shape.pdf(point)
+    }
+
+    fn sample_from(&self, reference: &Vector3, u: &Vector2) -> Option<ShapeSample> {
+        self.shape.sample_from(reference, u)
+    }
+
+    fn pdf_from(&self, reference: &Vector3, point: &Vector3, normal: &Vector3) -> Float {
+        self.shape.pdf_from(reference, point, normal)
+    }
+}
@@ -0,0 +1,94 @@
+use crate::shapes::shape::{accept_distance, plane_distance, ShapeSample};
+use crate::*;
+
+// The parallelogram spanned by the edges `u` and `v` from `corner`. The normal
+// follows the right-handed orientation of the edges.
+pub struct Quad {
+    pub corner: Vector3,
+    pub u: Vector3,
+    pub v: Vector3,
+    pub normal: Vector3,
+}
+
+impl Quad {
+    pub fn new(corner: Vector3, u: Vector3, v: Vector3) -> Quad {
+        let normal = u.cross(&v).normalize();
+        Quad {
+            corner,
+            u,
+            v,
+            normal,
+        }
+    }
+
+    pub fn area(&self) -> Float {
+        self.u.cross(&self.v).magnitude()
+    }
+
+    // Distance to the plane of the quad and the coordinates of the hit along both
+    // edges, which have to lie in [0, 1].
+    fn hit(&self, ray: &Ray, bound: &Interval) -> Option<(Float, Float, Float)> {
+        let (distance, error) = plane_distance(&self.corner, &self.normal, ray);
+        if !accept_distance(bound, distance, error) {
+            return None;
+        }
+
+        let n = self.u.cross(&self.v);
+        let w = n / n.dot(&n);
+        let offset = ray.at(distance) - self.corner;
+        let a = w.dot(&offset.cross(&self.v));
+        let b = w.dot(&self.u.cross(&offset));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+        Some((distance, a, b))
+    }
+}
+
+impl Shape for Quad {
+    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
+        let (distance, a, b) = self.hit(ray, bound)?;
+
+        // Rebuilding the point from the edge coordinates keeps it on the quad.
+        let point = self.corner + self.u * a + self.v * b;
+        let p_error =
+            (self.corner.abs() + (self.u * a).abs() + (self.v * b).abs()) * gamma(4);
+        let uv = Vector2::new([a, b]);
+
+        let mut intersection =
+            Intersection::new(ray, distance, point, self.normal, uv, self.u, self.v);
+        intersection.p_error = p_error;
+        Some(intersection)
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        self.hit(ray, bound).is_some()
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ]
+        .iter()
+        .fold(Bound::<3>::empty(), |b, p| b.union_point(p))
+    }
+
+    fn sample(&self, u: &Vector2) -> Option<ShapeSample> {
+        let area = self.area();
+        if area == 0.0 {
+            return None;
+        }
+        Some(ShapeSample {
+            point: self.corner + self.u * u[0] + self.v * u[1],
+            normal: self.normal,
+            pdf: area.recip(),
+        })
+    }
+
+    fn pdf(&self, _point: &Vector3) -> Float {
+        self.area().recip()
+    }
+}
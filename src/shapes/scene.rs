@@ -1,32 +1,217 @@
-use crate::lm::transform::Transform;
+use crate::lm::transform::{AnimatedTransform, Transform};
+use crate::rt::light::{DiffuseAreaLight, Light};
+use crate::rt::material::Material;
 use crate::*;
+use std::sync::Arc;
 
 pub struct Scene {
     pub shapes: Vec<Box<dyn Shape>>,
-    pub transforms: Vec<Transform>,
+    pub transforms: Vec<AnimatedTransform>,
+    pub lights: Vec<Arc<dyn Light>>,
 }
 
 impl Scene {
     pub fn new() -> Scene {
-        Scene { shapes: Vec::new() }
+        Scene {
+            shapes: Vec::new(),
+            transforms: Vec::new(),
+            lights: Vec::new(),
+        }
     }
 
     pub fn push(
-        mut self,
-        shape: impl Shape,
+        self,
+        shape: impl Shape + 'static,
         translation: &Vector3,
         rotation: &Vector3,
         scale: &Vector3,
-    ) {
+    ) -> Scene {
         let to_world = Transform::translate(translation)
             * Transform::rotate_x(rotation[0])
             * Transform::rotate_y(rotation[1])
             * Transform::rotate_z(rotation[2])
             * Transform::scale(scale[0], scale[1], scale[2]);
+        self.push_transformed(shape, to_world)
+    }
+
+    pub fn push_transformed(
+        self,
+        shape: impl Shape + 'static,
+        to_world: Transform,
+    ) -> Scene {
+        self.push_animated(shape, to_world.into())
+    }
+
+    pub fn push_animated(
+        mut self,
+        shape: impl Shape + 'static,
+        to_world: AnimatedTransform,
+    ) -> Scene {
+        self.shapes.push(Box::new(shape));
+        self.transforms.push(to_world);
+        self
+    }
+
+    pub fn push_light(mut self, light: impl Light + 'static) -> Scene {
+        self.lights.push(Arc::new(light));
         self
     }
+
+    // Adds the light's shape as geometry that reports the light when hit.
+    pub fn push_area_light(
+        mut self,
+        light: DiffuseAreaLight,
+        material: Arc<dyn Material>,
+    ) -> Scene {
+        let light = Arc::new(light);
+        self.lights.push(light.clone());
+        let primitive =
+            Primitive::new(light.shape.clone(), material).with_light(light.clone());
+        self.push_transformed(primitive, light.to_world)
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene::new()
+    }
 }
 
 impl Shape for Scene {
-    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {}
+    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
+        let mut intersection = None;
+        let mut b = *bound;
+        for (i, (shape, transform)) in
+            self.shapes.iter().zip(self.transforms.iter()).enumerate()
+        {
+            let transform = transform.interpolate(ray.time);
+            let (object_ray, object_bound, dt) = match object_ray(&transform, ray, &b) {
+                Some(object) => object,
+                None => continue,
+            };
+            if let Some(mut candidate) = shape.intersect(&object_ray, &object_bound) {
+                candidate.distance += dt;
+                b[0].1 = candidate.distance;
+                candidate.p_error =
+                    transform.apply_point_error(&candidate.point, &candidate.p_error);
+                candidate.point = transform.apply_point(&candidate.point);
+                candidate.normal = transform.apply_normal(&candidate.normal).normalize();
+                candidate.dpdu = transform.apply_vector(&candidate.dpdu);
+                candidate.dpdv = transform.apply_vector(&candidate.dpdv);
+                let shading = &mut candidate.shading;
+                shading.normal = transform.apply_normal(&shading.normal).normalize();
+                shading.dpdu = transform.apply_vector(&shading.dpdu);
+                shading.dpdv = transform.apply_vector(&shading.dpdv);
+                candidate.dpdx = transform.apply_vector(&candidate.dpdx);
+                candidate.dpdy = transform.apply_vector(&candidate.dpdy);
+                candidate.shape_id = i;
+                intersection = Some(candidate);
+            }
+        }
+
+        intersection
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        self.shapes
+            .iter()
+            .zip(self.transforms.iter())
+            .any(|(shape, transform)| {
+                let transform = transform.interpolate(ray.time);
+                match object_ray(&transform, ray, bound) {
+                    Some((object_ray, object_bound, _)) => {
+                        shape.intersect_p(&object_ray, &object_bound)
+                    }
+                    None => false,
+                }
+            })
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        self.shapes
+            .iter()
+            .zip(self.transforms.iter())
+            .fold(Bound::<3>::empty(), |b, (shape, transform)| {
+                b.union(&transform.motion_bound(&shape.bounds()))
+            })
+    }
+}
+
+// Directions are not renormalized, so distances agree in both spaces. The origin
+// is advanced past its rounding error in object space so that rays spawned from a
+// surface cannot hit it again, the returned offset has to be added back to the
+// object space distances. An interval that starts at or after the origin skips
+// the advanced part, a negative start is kept. Returns `None` when nothing of the
+// interval is left.
+fn object_ray(
+    transform: &Transform,
+    ray: &Ray,
+    bound: &Interval,
+) -> Option<(Ray, Interval, Float)> {
+    let mut object_ray = transform.apply_inverse_ray(ray);
+    let o_error = transform.apply_inverse_point_error(&ray.o, &Vector3::zero());
+    let length_squared = object_ray.d.dot(&object_ray.d);
+    let dt = if length_squared > 0.0 {
+        object_ray.d.abs().dot(&o_error) / length_squared
+    } else {
+        0.0
+    };
+    object_ray.o = object_ray.o + object_ray.d * dt;
+    let (min, max) = bound[0];
+    let min = if min < 0.0 {
+        min - dt
+    } else {
+        (min - dt).max(0.0)
+    };
+    // `Interval::new` would reorder the ends, an empty interval has to stay empty.
+    let object_bound = Interval {
+        b: [(min, max - dt)],
+    };
+    if object_bound[0].0 > object_bound[0].1 {
+        return None;
+    }
+    Some((object_ray, object_bound, dt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scene() -> Scene {
+        Scene::new().push(
+            Sphere::new(Vector3::zero(), 1.0),
+            &Vector3::new([0.1, 0.2, 0.3]),
+            &Vector3::new([10.0, 20.0, 30.0]),
+            &Vector3::new([1.5, 1.5, 1.5]),
+        )
+    }
+
+    #[test]
+    fn empty_intervals_have_no_hits() {
+        let scene = scene();
+        let ray = Ray::new(
+            Vector3::new([0.1, 0.2, -3.0]),
+            Vector3::new([0.0, 0.0, 1.0]),
+        );
+        let hit = scene.intersect(&ray, &Interval::new([(0.0, Float::INFINITY)]));
+        assert!((hit.unwrap().distance - 1.8).abs() < 1E-9);
+
+        let reversed = Interval { b: [(3.0, 1.0)] };
+        assert!(scene.intersect(&ray, &reversed).is_none());
+        assert!(!scene.intersect_p(&ray, &reversed));
+    }
+
+    #[test]
+    fn negative_lower_bounds_are_kept() {
+        let scene = scene();
+        let ray = Ray::new(Vector3::new([0.1, 0.2, 3.0]), Vector3::new([0.0, 0.0, 1.0]));
+        assert!(scene
+            .intersect(&ray, &Interval::new([(0.0, Float::INFINITY)]))
+            .is_none());
+
+        let behind = Interval::new([(-10.0, Float::INFINITY)]);
+        let hit = scene.intersect(&ray, &behind).unwrap();
+        assert!((hit.distance + 4.2).abs() < 1E-9);
+        assert!(scene.intersect_p(&ray, &behind));
+    }
 }
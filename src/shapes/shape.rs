@@ -0,0 +1,249 @@
+use crate::rt::ray::{Intersection, Ray};
+use crate::*;
+use std::sync::Arc;
+
+// A point on a surface with its geometric normal, which is oriented like the
+// normal of an intersection at the same point.
+#[derive(Copy, Clone, Debug)]
+pub struct ShapeSample {
+    pub point: Vector3,
+    pub normal: Vector3,
+    pub pdf: Float,
+}
+
+pub trait Shape: Send + Sync {
+    // The nearest hit within `bound`, which may include negative distances. When
+    // it contains zero, hits that cannot be told apart from the ray origin given
+    // their rounding error are skipped, so that rays leaving a surface do not hit
+    // it again.
+    fn intersect(&self, ray: &Ray, bound: &Bound<1>) -> Option<Intersection>;
+
+    // Any hit within the interval, which lets visibility tests stop early and skip
+    // building the intersection record.
+    fn intersect_p(&self, ray: &Ray, bound: &Bound<1>) -> bool {
+        self.intersect(ray, bound).is_some()
+    }
+
+    fn bounds(&self) -> Bound<3>;
+
+    // Picks a point on the surface with a density with respect to area. Shapes
+    // that cannot be sampled, such as infinite ones, return `None`.
+    fn sample(&self, _u: &Vector2) -> Option<ShapeSample> {
+        None
+    }
+
+    // The area density of `sample` at a point on the surface.
+    fn pdf(&self, _point: &Vector3) -> Float {
+        0.0
+    }
+
+    // Picks a point as seen from `reference` with a density with respect to
+    // solid angle there. Shapes can do better than sampling their area when they
+    // know which part of them is visible.
+    fn sample_from(&self, reference: &Vector3, u: &Vector2) -> Option<ShapeSample> {
+        let mut sample = self.sample(u)?;
+        sample.pdf =
+            area_to_solid_angle(sample.pdf, reference, &sample.point, &sample.normal);
+        if sample.pdf == 0.0 || !sample.pdf.is_finite() {
+            return None;
+        }
+        Some(sample)
+    }
+
+    // The solid angle density of `sample_from` choosing `point`.
+    fn pdf_from(&self, reference: &Vector3, point: &Vector3, normal: &Vector3) -> Float {
+        let pdf = area_to_solid_angle(self.pdf(point), reference, point, normal);
+        if pdf.is_finite() {
+            pdf
+        } else {
+            0.0
+        }
+    }
+}
+
+// Whether a hit at `distance` with an absolute rounding `error` is reported for
+// `bound`, see `Shape::intersect`.
+pub(crate) fn accept_distance(bound: &Interval, distance: Float, error: Float) -> bool {
+    distance.is_finite()
+        && bound.contains(&Vector1::new([distance]))
+        && !(distance.abs() <= error && bound.contains(&Vector1::zero()))
+}
+
+// Distance along the ray to the plane through `point` with `normal`, and a bound
+// on its rounding error.
+pub(crate) fn plane_distance(
+    point: &Vector3,
+    normal: &Vector3,
+    ray: &Ray,
+) -> (Float, Float) {
+    let denominator = ray.d.dot(normal);
+    let distance = (*point - ray.o).dot(normal) / denominator;
+    let n = normal.abs();
+    let error = gamma(4)
+        * ((point.abs() + ray.o.abs()).dot(&n) + distance.abs() * ray.d.abs().dot(&n))
+        / denominator.abs();
+    (distance, error)
+}
+
+// Converts a density with respect to the area around `point` to one with respect
+// to the solid angle it subtends at `reference`.
+pub fn area_to_solid_angle(
+    pdf: Float,
+    reference: &Vector3,
+    point: &Vector3,
+    normal: &Vector3,
+) -> Float {
+    let d = *point - *reference;
+    let distance_squared = d.dot(&d);
+    let cos_theta = normal.dot(&d).abs() / distance_squared.sqrt();
+    pdf * distance_squared / cos_theta
+}
+
+impl<T: Shape + ?Sized> Shape for Arc<T> {
+    fn intersect(&self, ray: &Ray, bound: &Bound<1>) -> Option<Intersection> {
+        (**self).intersect(ray, bound)
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Bound<1>) -> bool {
+        (**self).intersect_p(ray, bound)
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        (**self).bounds()
+    }
+
+    fn sample(&self, u: &Vector2) -> Option<ShapeSample> {
+        (**self).sample(u)
+    }
+
+    fn pdf(&self, point: &Vector3) -> Float {
+        (**self).pdf(point)
+    }
+
+    fn sample_from(&self, reference: &Vector3, u: &Vector2) -> Option<ShapeSample> {
+        (**self).sample_from(reference, u)
+    }
+
+    fn pdf_from(&self, reference: &Vector3, point: &Vector3, normal: &Vector3) -> Float {
+        (**self).pdf_from(reference, point, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lm::rng::Rng;
+    use crate::lm::sampling::uniform_sphere;
+    use std::f64::consts::PI;
+
+    fn shapes() -> Vec<(&'static str, Box<dyn Shape>)> {
+        let a = Vector3::new([-1.0, -0.5, 0.0]);
+        let b = Vector3::new([1.5, -0.5, 0.25]);
+        let c = Vector3::new([0.0, 1.0, -0.25]);
+        let d = Vector3::new([-1.0, 1.5, 0.5]);
+        vec![
+            (
+                "sphere",
+                Box::new(Sphere::new(Vector3::new([0.0, 0.0, 0.5]), 0.75)),
+            ),
+            ("triangle", Box::new(Triangle::build([a, b, c]))),
+            ("quad", Box::new(Quad::new(a, b - a, c - a))),
+            (
+                "mesh",
+                Box::new(
+                    Mesh::from_triangles(&[
+                        Triangle::build([a, b, c]),
+                        Triangle::build([a, c, d]),
+                    ])
+                    .unwrap(),
+                ),
+            ),
+            (
+                "mesh triangle",
+                Box::new(
+                    Mesh::from_triangles(&[Triangle::build([a, b, c])])
+                        .unwrap()
+                        .triangle(0),
+                ),
+            ),
+        ]
+    }
+
+    fn references() -> [Vector3; 3] {
+        [
+            Vector3::new([0.2, 0.1, 2.0]),
+            Vector3::new([-0.5, 0.3, -1.5]),
+            Vector3::new([0.1, 0.0, 0.4]),
+        ]
+    }
+
+    fn assert_relative(a: Float, b: Float, name: &str) {
+        assert!(
+            (a - b).abs() <= 1E-9 * a.abs().max(b.abs()),
+            "{name}: {a} != {b}"
+        );
+    }
+
+    #[test]
+    fn sample_densities_match_pdfs() {
+        let mut rng = Rng::new(21);
+        for (name, shape) in shapes() {
+            for _ in 0..500 {
+                let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+                let sample = shape.sample(&u).unwrap();
+                assert_relative(sample.pdf, shape.pdf(&sample.point), name);
+
+                for reference in references() {
+                    let sample = match shape.sample_from(&reference, &u) {
+                        Some(sample) => sample,
+                        None => continue,
+                    };
+                    let pdf = shape.pdf_from(&reference, &sample.point, &sample.normal);
+                    assert_relative(sample.pdf, pdf, name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intervals_may_include_negative_distances() {
+        let ray = Ray::new(Vector3::new([0.1, 0.1, 5.0]), Vector3::new([0.0, 0.0, 1.0]));
+        let ahead = Interval::new([(0.0, Float::INFINITY)]);
+        let behind = Interval::new([(Float::NEG_INFINITY, Float::INFINITY)]);
+        for (name, shape) in shapes() {
+            assert!(shape.intersect(&ray, &ahead).is_none(), "{name}");
+            assert!(!shape.intersect_p(&ray, &ahead), "{name}");
+
+            let hit = shape.intersect(&ray, &behind).unwrap();
+            assert!(hit.distance < -4.0, "{name}: {}", hit.distance);
+            assert!(
+                (ray.at(hit.distance) - hit.point).magnitude() < 1E-9,
+                "{name}"
+            );
+            assert!(shape.intersect_p(&ray, &behind), "{name}");
+        }
+    }
+
+    // Integrates `pdf_from` over the directions around each reference point, which
+    // gives one whenever the shape is visible along every direction at most once.
+    #[test]
+    fn solid_angle_densities_integrate_to_one() {
+        let mut rng = Rng::new(22);
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        let count = 100_000;
+        for (name, shape) in shapes() {
+            for reference in references() {
+                let mut sum = 0.0;
+                for _ in 0..count {
+                    let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+                    let ray = Ray::new(reference, uniform_sphere(&u));
+                    if let Some(hit) = shape.intersect(&ray, &bound) {
+                        sum += shape.pdf_from(&reference, &hit.point, &hit.normal);
+                    }
+                }
+                let integral = sum * 4.0 * PI / count as Float;
+                assert!((integral - 1.0).abs() < 0.05, "{name}: {integral}");
+            }
+        }
+    }
+}
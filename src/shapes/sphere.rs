@@ -0,0 +1,235 @@
+use crate::lm::sampling::uniform_sphere;
+use crate::shapes::shape::{accept_distance, area_to_solid_angle, ShapeSample};
+use crate::*;
+use std::f64::consts::PI;
+
+// Below this squared sine, about 1.5 degrees, 1 - cos loses its precision and the
+// cone is handled with the Taylor expansion instead.
+const SMALL_CONE: Float = 0.000_685_23;
+
+pub struct Sphere {
+    pub center: Vector3,
+    pub radius: Float,
+}
+
+impl Sphere {
+    pub fn new(center: Vector3, radius: Float) -> Sphere {
+        Sphere {
+            center,
+            radius: radius.abs(),
+        }
+    }
+
+    pub fn area(&self) -> Float {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    // Squared sine of the half-angle of the cone the sphere subtends at
+    // `reference`, or `None` when the point lies inside.
+    fn sin_2_theta_max(&self, reference: &Vector3) -> Option<Float> {
+        let d = self.center - *reference;
+        let sin_2_theta_max = self.radius * self.radius / d.dot(&d);
+        (sin_2_theta_max < 1.0).then_some(sin_2_theta_max)
+    }
+
+    // Nearest root of the ray-sphere quadratic within the interval, see
+    // `Shape::intersect` for roots close to zero.
+    fn distance(&self, ray: &Ray, bound: &Interval) -> Option<Float> {
+        let oc = ray.o - self.center;
+        let a = ray.d.dot(&ray.d);
+        let half_b = ray.d.dot(&oc);
+        let length = oc.magnitude();
+        let c = (length - self.radius) * (length + self.radius);
+
+        let discriminant = sub_mul_pair(half_b, half_b, a, c);
+        if discriminant <= 0.0 {
+            return None;
+        }
+
+        // Avoid cancellation by computing the root of larger magnitude first and
+        // deriving the other one from the product of roots.
+        let root = discriminant.sqrt();
+        let q = -(half_b + root.copysign(half_b));
+        let (t_0, t_1) = ((q / a).min(c / q), (q / a).max(c / q));
+
+        // Both roots move by the error of c and b over the slope of the quadratic,
+        // which is 2 * root at either of them.
+        let oc_error = (ray.o.abs() + self.center.abs()) * gamma(1);
+        let length_error = oc_error.magnitude() + gamma(2) * length;
+        let sum = length + self.radius;
+        let c_error = 2.0 * (length_error + gamma(1) * sum) * sum + gamma(1) * c.abs();
+        let half_b_error = ray.d.abs().dot(&(oc_error + oc.abs() * gamma(3)));
+        let delta_t = |t: Float| {
+            (c_error + 2.0 * t.abs() * half_b_error) / (2.0 * root) + gamma(7) * t.abs()
+        };
+
+        [t_0, t_1]
+            .into_iter()
+            .find(|&t| accept_distance(bound, t, delta_t(t)))
+    }
+}
+
+impl Shape for Sphere {
+    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
+        let distance = self.distance(ray, bound)?;
+
+        // Project the hit back onto the surface to bound its error.
+        let mut p = ray.at(distance) - self.center;
+        p = p * (self.radius / p.magnitude());
+        let point = self.center + p;
+        let p_error = p.abs() * gamma(5) + self.center.abs() * gamma(1);
+        let normal = p.normalize();
+
+        // Spherical coordinates around the +y axis, u follows the longitude and v
+        // runs from the top pole to the bottom one.
+        if p[0] == 0.0 && p[2] == 0.0 {
+            p[0] = 1E-5 * self.radius;
+        }
+        let phi = p[2].atan2(p[0]).rem_euclid(2.0 * PI);
+        let theta = (p[1] / self.radius).clamp(-1.0, 1.0).acos();
+        let uv = Vector2::new([phi / (2.0 * PI), theta / PI]);
+
+        let rho = (p[0] * p[0] + p[2] * p[2]).sqrt();
+        let dpdu = Vector3::new([-p[2], 0.0, p[0]]) * (2.0 * PI);
+        let dpdv = Vector3::new([p[1] * p[0] / rho, -rho, p[1] * p[2] / rho]) * PI;
+
+        let mut intersection =
+            Intersection::new(ray, distance, point, normal, uv, dpdu, dpdv);
+        intersection.p_error = p_error;
+        Some(intersection)
+    }
+
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        self.distance(ray, bound).is_some()
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        let r = Vector3::new([self.radius, self.radius, self.radius]);
+        Bound::<3>::empty()
+            .union_point(&(self.center - r))
+            .union_point(&(self.center + r))
+    }
+
+    fn sample(&self, u: &Vector2) -> Option<ShapeSample> {
+        let normal = uniform_sphere(u);
+        Some(ShapeSample {
+            point: self.center + normal * self.radius,
+            normal,
+            pdf: self.area().recip(),
+        })
+    }
+
+    fn pdf(&self, _point: &Vector3) -> Float {
+        self.area().recip()
+    }
+
+    // Points outside the sphere sample the cone of directions it subtends, and the
+    // sampled direction is turned into the angle at the centre which gives the
+    // point on the visible cap directly.
+    fn sample_from(&self, reference: &Vector3, u: &Vector2) -> Option<ShapeSample> {
+        let sin_2_theta_max = match self.sin_2_theta_max(reference) {
+            Some(sin_2_theta_max) => sin_2_theta_max,
+            None => {
+                let mut sample = self.sample(u)?;
+                sample.pdf = area_to_solid_angle(
+                    sample.pdf,
+                    reference,
+                    &sample.point,
+                    &sample.normal,
+                );
+                return sample.pdf.is_finite().then_some(sample);
+            }
+        };
+
+        let sin_theta_max = sin_2_theta_max.sqrt();
+        let cos_theta_max = (1.0 - sin_2_theta_max).sqrt();
+        let mut cos_theta = (cos_theta_max - 1.0) * u[0] + 1.0;
+        let mut sin_2_theta = 1.0 - cos_theta * cos_theta;
+        if sin_2_theta_max < SMALL_CONE {
+            sin_2_theta = sin_2_theta_max * u[0];
+            cos_theta = (1.0 - sin_2_theta).sqrt();
+        }
+
+        let cos_alpha = sin_2_theta / sin_theta_max
+            + cos_theta * (1.0 - sin_2_theta / sin_2_theta_max).max(0.0).sqrt();
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+        let phi = 2.0 * PI * u[1];
+        let local =
+            Vector3::new([sin_alpha * phi.cos(), sin_alpha * phi.sin(), cos_alpha]);
+        let d = self.center - *reference;
+        let normal = -Frame::from_z(&d).from_local(&local);
+
+        Some(ShapeSample {
+            point: self.center + normal * self.radius,
+            normal,
+            pdf: cone_pdf(sin_2_theta_max),
+        })
+    }
+
+    fn pdf_from(&self, reference: &Vector3, point: &Vector3, normal: &Vector3) -> Float {
+        match self.sin_2_theta_max(reference) {
+            Some(sin_2_theta_max) => cone_pdf(sin_2_theta_max),
+            None => {
+                let pdf = area_to_solid_angle(self.pdf(point), reference, point, normal);
+                if pdf.is_finite() {
+                    pdf
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+fn cone_pdf(sin_2_theta_max: Float) -> Float {
+    let one_minus_cos_theta_max = if sin_2_theta_max < SMALL_CONE {
+        sin_2_theta_max / 2.0
+    } else {
+        1.0 - (1.0 - sin_2_theta_max).sqrt()
+    };
+    1.0 / (2.0 * PI * one_minus_cos_theta_max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lm::rng::Rng;
+
+    #[test]
+    fn rays_leaving_the_surface_do_not_hit_it_again() {
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        let sphere = Sphere::new(Vector3::new([1.0, -2.0, 3.0]), 0.75);
+        let mut rng = Rng::new(11);
+
+        for _ in 0..1000 {
+            let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+            let normal = uniform_sphere(&u);
+            // Start exactly at the rounded surface point, without any offset.
+            let o = sphere.center + normal * sphere.radius;
+            let u = Vector2::new([rng.uniform_float(), rng.uniform_float()]);
+            let mut d = uniform_sphere(&u);
+            if d.dot(&normal) < 0.0 {
+                d = -d;
+            }
+            assert!(sphere.distance(&Ray::new(o, d), &bound).is_none());
+            // Going inwards only the far side is found.
+            if let Some(t) = sphere.distance(&Ray::new(o, -d), &bound) {
+                assert!(t > 1E-6);
+            }
+        }
+    }
+
+    #[test]
+    fn origin_on_the_surface_and_tangent_rays() {
+        let sphere = Sphere::new(Vector3::zero(), 1.0);
+        let ray = Ray::new(
+            Vector3::new([0.0, 0.0, -1.0]),
+            Vector3::new([0.0, 0.0, 1.0]),
+        );
+        let bound = Interval::new([(0.0, Float::INFINITY)]);
+        assert_eq!(sphere.distance(&ray, &bound), Some(2.0));
+        let tangent =
+            Ray::new(Vector3::new([1.0, 0.0, 0.0]), Vector3::new([0.0, 1.0, 0.0]));
+        assert_eq!(sphere.distance(&tangent, &bound), None);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::lm::sampling::uniform_triangle;
+use crate::shapes::shape::{accept_distance, ShapeSample};
 use crate::*;
 
 #[derive(Copy, Clone, Debug)]
@@ -20,40 +22,274 @@ impl Triangle {
     }
 }
 
-impl Shape for Triangle {
-    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
-        let e_1 = self.vertices[1] - self.vertices[0];
-        let e_2 = self.vertices[2] - self.vertices[0];
-        let s = ray.o - self.vertices[0];
-        let s_1 = ray.d.cross(&e_2);
-        let s_2 = s.cross(&e_1);
+// Parameterisation used when a triangle has no texture coordinates.
+pub(crate) const DEFAULT_UVS: [Vector2; 3] = [
+    Vector2 { v: [0.0, 0.0] },
+    Vector2 { v: [1.0, 0.0] },
+    Vector2 { v: [1.0, 1.0] },
+];
 
-        let det_recip = e_1.dot(&s_1).recip();
+// Solves for the constant dp/du and dp/dv over the triangle. Degenerate uvs fall
+// back to an arbitrary frame around the geometric normal.
+pub(crate) fn triangle_partials(
+    vertices: &[Vector3; 3],
+    uvs: &[Vector2; 3],
+) -> (Vector3, Vector3) {
+    let duv_02 = uvs[0] - uvs[2];
+    let duv_12 = uvs[1] - uvs[2];
+    let dp_02 = vertices[0] - vertices[2];
+    let dp_12 = vertices[1] - vertices[2];
 
-        let u = s_1.dot(&s) * det_recip;
-        if u < 0.0 || u > 1.0 {
-            return None;
+    let det = sub_mul_pair(duv_02[0], duv_12[1], duv_02[1], duv_12[0]);
+    if det.abs() > 1E-9 {
+        let det_recip = det.recip();
+        let dpdu = (dp_02 * duv_12[1] - dp_12 * duv_02[1]) * det_recip;
+        let dpdv = (dp_12 * duv_02[0] - dp_02 * duv_12[0]) * det_recip;
+        if dpdu.cross(&dpdv).magnitude() > 0.0 {
+            return (dpdu, dpdv);
         }
+    }
+
+    (vertices[1] - vertices[0])
+        .cross(&(vertices[2] - vertices[0]))
+        .normalize()
+        .coordinate_system()
+}
+
+// Watertight test (Woop et al.): the vertices are moved into a space where the
+// ray starts at the origin and runs along +z, so that neighbouring triangles
+// evaluate the edge functions of a shared edge identically. Returns the distance
+// and the barycentric coordinates of vertices 1 and 2.
+pub(crate) fn intersect_triangle(
+    ray: &Ray,
+    bound: &Interval,
+    vertices: &[Vector3; 3],
+) -> Option<(Float, Float, Float)> {
+    if (vertices[2] - vertices[0])
+        .cross(&(vertices[1] - vertices[0]))
+        .magnitude()
+        == 0.0
+    {
+        return None;
+    }
 
-        let v = s_2.dot(&ray.d) * det_recip;
-        if v < 0.0 || u + v > 1.0 {
-            return None;
+    // Permute so that the largest direction component ends up in z.
+    let abs_d = [ray.d[0].abs(), ray.d[1].abs(), ray.d[2].abs()];
+    let kz = if abs_d[0] > abs_d[1] {
+        if abs_d[0] > abs_d[2] {
+            0
+        } else {
+            2
         }
+    } else if abs_d[1] > abs_d[2] {
+        1
+    } else {
+        2
+    };
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+    let permute = |v: &Vector3| Vector3::new([v[kx], v[ky], v[kz]]);
+    let d = permute(&ray.d);
+    let mut p = vertices.map(|v| permute(&(v - ray.o)));
+
+    // Shear so the ray direction becomes +z, z is only sheared when needed.
+    let shear = [-d[0] / d[2], -d[1] / d[2], d[2].recip()];
+    for p in p.iter_mut() {
+        p[0] += shear[0] * p[2];
+        p[1] += shear[1] * p[2];
+    }
+
+    let mut e = [
+        p[1][0] * p[2][1] - p[1][1] * p[2][0],
+        p[2][0] * p[0][1] - p[2][1] * p[0][0],
+        p[0][0] * p[1][1] - p[0][1] * p[1][0],
+    ];
+    // An edge function that rounds to zero is recomputed with the error-free
+    // product difference before deciding on which side the ray passes.
+    if e.contains(&0.0) {
+        e = [
+            sub_mul_pair(p[1][0], p[2][1], p[1][1], p[2][0]),
+            sub_mul_pair(p[2][0], p[0][1], p[2][1], p[0][0]),
+            sub_mul_pair(p[0][0], p[1][1], p[0][1], p[1][0]),
+        ];
+    }
+    if e.iter().any(|&e| e < 0.0) && e.iter().any(|&e| e > 0.0) {
+        return None;
+    }
+    let det = e[0] + e[1] + e[2];
+    if det == 0.0 {
+        return None;
+    }
+
+    for p in p.iter_mut() {
+        p[2] *= shear[2];
+    }
+    let t_scaled = e[0] * p[0][2] + e[1] * p[1][2] + e[2] * p[2][2];
+    let det_recip = det.recip();
+    let distance = t_scaled * det_recip;
+
+    // Bound the rounding error of the distance, see `Shape::intersect`.
+    let max_z = p.iter().fold(0.0, |m: Float, p| m.max(p[2].abs()));
+    let max_x = p.iter().fold(0.0, |m: Float, p| m.max(p[0].abs()));
+    let max_y = p.iter().fold(0.0, |m: Float, p| m.max(p[1].abs()));
+    let delta_z = gamma(3) * max_z;
+    let delta_x = gamma(5) * (max_x + max_z);
+    let delta_y = gamma(5) * (max_y + max_z);
+    let delta_e = 2.0 * (gamma(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+    let max_e = e.iter().fold(0.0, |m: Float, e| m.max(e.abs()));
+    let delta_t = 3.0
+        * (gamma(3) * max_e * max_z + delta_e * max_z + delta_z * max_e)
+        * det_recip.abs();
+    if !accept_distance(bound, distance, delta_t) {
+        return None;
+    }
+
+    Some((distance, e[1] * det_recip, e[2] * det_recip))
+}
+
+// Builds the hit record from the barycentric coordinates (u, v) of vertices 1
+// and 2. Vertex normals, when present, give the shading normal and its tangents.
+pub(crate) fn triangle_intersection(
+    ray: &Ray,
+    hit: (Float, Float, Float),
+    vertices: &[Vector3; 3],
+    normals: Option<&[Vector3; 3]>,
+    uvs: &[Vector2; 3],
+) -> Intersection {
+    let (distance, u, v) = hit;
+    let b = [1.0 - u - v, u, v];
+    // Interpolating the vertices is more accurate than stepping along the ray.
+    let point = b[0] * vertices[0] + b[1] * vertices[1] + b[2] * vertices[2];
+    let p_error = ((b[0] * vertices[0]).abs()
+        + (b[1] * vertices[1]).abs()
+        + (b[2] * vertices[2]).abs())
+        * gamma(7);
+    let uv = b[0] * uvs[0] + b[1] * uvs[1] + b[2] * uvs[2];
+    let (dpdu, dpdv) = triangle_partials(vertices, uvs);
+    let normal = (vertices[1] - vertices[0])
+        .cross(&(vertices[2] - vertices[0]))
+        .normalize();
+
+    let mut intersection =
+        Intersection::new(ray, distance, point, normal, uv, dpdu, dpdv);
+    intersection.p_error = p_error;
+    if let Some(n) = normals {
+        let shading_normal = (b[0] * n[0] + b[1] * n[1] + b[2] * n[2]).normalize();
+        if shading_normal.magnitude().is_finite() {
+            // The partials keep their length and lose the component along the
+            // shading normal.
+            let project = |v: &Vector3| *v - shading_normal * shading_normal.dot(v);
+            intersection.set_shading(shading_normal, project(&dpdu), project(&dpdv));
+        }
+    }
+    intersection
+}
+
+pub(crate) fn triangle_area(vertices: &[Vector3; 3]) -> Float {
+    0.5 * (vertices[1] - vertices[0])
+        .cross(&(vertices[2] - vertices[0]))
+        .magnitude()
+}
+
+// Uniform by area. The normal is flipped towards the vertex normals like the one
+// of an intersection.
+pub(crate) fn sample_triangle(
+    vertices: &[Vector3; 3],
+    normals: Option<&[Vector3; 3]>,
+    u: &Vector2,
+) -> Option<ShapeSample> {
+    let area = triangle_area(vertices);
+    if area == 0.0 {
+        return None;
+    }
 
-        let distance = s_2.dot(&e_2) * det_recip;
-        if !bound.contains(&Vector1::new([distance])) {
-            return None;
+    let b = uniform_triangle(u);
+    let point = b[0] * vertices[0] + b[1] * vertices[1] + b[2] * vertices[2];
+    let mut normal = (vertices[1] - vertices[0])
+        .cross(&(vertices[2] - vertices[0]))
+        .normalize();
+    if let Some(n) = normals {
+        if normal.dot(&(b[0] * n[0] + b[1] * n[1] + b[2] * n[2])) < 0.0 {
+            normal = -normal;
         }
+    }
 
-        let point = ray.at(distance);
-        let normal =
-            ((1.0 - u - v) * self.normals[0] + u * self.normals[1] + v * self.normals[2])
-                .normalize();
+    Some(ShapeSample {
+        point,
+        normal,
+        pdf: area.recip(),
+    })
+}
+
+impl Triangle {
+    pub fn area(&self) -> Float {
+        triangle_area(&self.vertices)
+    }
+}
+
+impl Shape for Triangle {
+    fn intersect(&self, ray: &Ray, bound: &Interval) -> Option<Intersection> {
+        let hit = intersect_triangle(ray, bound, &self.vertices)?;
+        Some(triangle_intersection(
+            ray,
+            hit,
+            &self.vertices,
+            Some(&self.normals),
+            &DEFAULT_UVS,
+        ))
+    }
 
-        Some(Intersection {
-            distance,
-            point,
-            normal,
-        })
+    fn intersect_p(&self, ray: &Ray, bound: &Interval) -> bool {
+        intersect_triangle(ray, bound, &self.vertices).is_some()
+    }
+
+    fn bounds(&self) -> Bound<3> {
+        self.vertices
+            .iter()
+            .fold(Bound::<3>::empty(), |b, v| b.union_point(v))
+    }
+
+    fn sample(&self, u: &Vector2) -> Option<ShapeSample> {
+        sample_triangle(&self.vertices, Some(&self.normals), u)
+    }
+
+    fn pdf(&self, _point: &Vector3) -> Float {
+        self.area().recip()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shading_partials_lie_in_the_shading_plane() {
+        let vertices = [
+            Vector3::new([0.0, 0.0, 0.0]),
+            Vector3::new([4.0, 0.0, 0.0]),
+            Vector3::new([0.0, 2.0, 0.0]),
+        ];
+        let normals = [
+            Vector3::new([0.3, 0.0, 1.0]).normalize(),
+            Vector3::new([0.0, 0.3, 1.0]).normalize(),
+            Vector3::new([-0.3, -0.3, 1.0]).normalize(),
+        ];
+        let triangle = Triangle::new(vertices, normals);
+        let ray = Ray::new(
+            Vector3::new([1.0, 0.5, 1.0]),
+            Vector3::new([0.0, 0.0, -1.0]),
+        );
+        let hit = triangle
+            .intersect(&ray, &Interval::new([(0.0, Float::INFINITY)]))
+            .unwrap();
+
+        let shading = hit.shading;
+        for (partial, geometric) in [(shading.dpdu, hit.dpdu), (shading.dpdv, hit.dpdv)] {
+            assert!(partial.dot(&shading.normal).abs() < 1E-12);
+            // Only the component along the shading normal is removed.
+            let removed = geometric - partial;
+            assert!(removed.cross(&shading.normal).magnitude() < 1E-12);
+            assert!(partial.magnitude() > 0.9 * geometric.magnitude());
+        }
     }
 }
@@ -0,0 +1,776 @@
+use crate::*;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_token<R: BufRead>(reader: &mut R) -> Result<String, Error> {
+    let mut token = String::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0].is_ascii_whitespace() {
+            if token.is_empty() {
+                continue;
+            }
+            return Ok(token);
+        }
+        token.push(byte[0] as char);
+    }
+}
+
+// Portable FloatMap: rows are stored bottom to top, a negative scale marks
+// little-endian samples.
+pub fn write_pfm(
+    path: &str,
+    width: usize,
+    height: usize,
+    data: &[Vector3],
+) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(format!("PF\n{width} {height}\n-1.0\n").as_bytes())?;
+    for y in (0..height).rev() {
+        for v in &data[y * width..(y + 1) * width] {
+            for c in v.v {
+                file.write_all(&(c as f32).to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+pub fn read_pfm(path: &str) -> Result<(usize, usize, Vec<Vector3>), Error> {
+    let mut file = BufReader::new(File::open(path)?);
+    let channels = match read_token(&mut file)?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("Not a PFM file.")),
+    };
+    let width = read_token(&mut file)?
+        .parse::<usize>()
+        .map_err(|_| invalid("Invalid PFM width."))?;
+    let height = read_token(&mut file)?
+        .parse::<usize>()
+        .map_err(|_| invalid("Invalid PFM height."))?;
+    let scale = read_token(&mut file)?
+        .parse::<f32>()
+        .map_err(|_| invalid("Invalid PFM scale."))?;
+
+    let mut bytes = vec![0u8; width * height * channels * 4];
+    file.read_exact(&mut bytes)?;
+    let samples = bytes
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            let v = if scale < 0.0 {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            };
+            (v * scale.abs()) as Float
+        })
+        .collect::<Vec<Float>>();
+
+    let mut data = vec![Vector3::zero(); width * height];
+    for (i, pixel) in samples.chunks_exact(channels).enumerate() {
+        let (x, y) = (i % width, height - 1 - i / width);
+        data[x + y * width] = match channels {
+            3 => Vector3::new([pixel[0], pixel[1], pixel[2]]),
+            _ => Vector3::new([pixel[0], pixel[0], pixel[0]]),
+        };
+    }
+
+    Ok((width, height, data))
+}
+
+// NaN and negative components are stored as zero, anything beyond the largest
+// exponent, infinity included, saturates.
+fn to_rgbe(v: &Vector3) -> [u8; 4] {
+    let c = v.v.map(|c| if c.is_nan() { 0.0 } else { c.max(0.0) });
+    let max = c[0].max(c[1]).max(c[2]);
+    if max < 1E-32 {
+        return [0, 0, 0, 0];
+    }
+
+    let exponent = (max.log2().floor() + 1.0).min(127.0) as i32;
+    let scale = 256.0 * 2.0_f64.powi(-exponent);
+    [
+        (c[0] * scale).min(255.0) as u8,
+        (c[1] * scale).min(255.0) as u8,
+        (c[2] * scale).min(255.0) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+fn from_rgbe(rgbe: &[u8]) -> Vector3 {
+    if rgbe[3] == 0 {
+        return Vector3::zero();
+    }
+
+    let f = 2.0_f64.powi(rgbe[3] as i32 - (128 + 8));
+    Vector3::new([
+        (rgbe[0] as Float + 0.5) * f,
+        (rgbe[1] as Float + 0.5) * f,
+        (rgbe[2] as Float + 0.5) * f,
+    ])
+}
+
+// Radiance RGBE, written as flat scanlines which every reader accepts.
+pub fn write_hdr(
+    path: &str,
+    width: usize,
+    height: usize,
+    data: &[Vector3],
+) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n")?;
+    file.write_all(format!("-Y {height} +X {width}\n").as_bytes())?;
+    for v in &data[..width * height] {
+        file.write_all(&to_rgbe(v))?;
+    }
+    file.flush()
+}
+
+pub fn read_hdr(path: &str) -> Result<(usize, usize, Vec<Vector3>), Error> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    file.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("Not a Radiance HDR file."));
+    }
+
+    loop {
+        line.clear();
+        if file.read_line(&mut line)? == 0 {
+            return Err(invalid("Unexpected end of HDR header."));
+        }
+        let l = line.trim();
+        if l.is_empty() {
+            break;
+        }
+        if l.starts_with("FORMAT=") && l != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid("Unsupported HDR pixel format."));
+        }
+    }
+
+    line.clear();
+    file.read_line(&mut line)?;
+    let resolution = line.split_whitespace().collect::<Vec<&str>>();
+    let (height, width) = match resolution.as_slice() {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>()
+                .map_err(|_| invalid("Invalid HDR height."))?,
+            w.parse::<usize>()
+                .map_err(|_| invalid("Invalid HDR width."))?,
+        ),
+        _ => return Err(invalid("Unsupported HDR orientation.")),
+    };
+
+    let mut data = Vec::with_capacity(width * height);
+    let mut scanline = vec![0u8; 4 * width];
+    for _ in 0..height {
+        read_scanline(&mut file, &mut scanline, width)?;
+        data.extend(scanline.chunks_exact(4).map(from_rgbe));
+    }
+
+    Ok((width, height, data))
+}
+
+// Reads either a flat scanline or one in the adaptive run-length encoding where
+// each of the four components is stored separately.
+fn read_scanline<R: Read>(
+    file: &mut R,
+    scanline: &mut [u8],
+    width: usize,
+) -> Result<(), Error> {
+    if width == 0 {
+        return Ok(());
+    }
+
+    let mut header = [0u8; 4];
+    file.read_exact(&mut header)?;
+    let encoded = (8..0x8000).contains(&width)
+        && header[0] == 2
+        && header[1] == 2
+        && header[2] & 0x80 == 0;
+    if !encoded {
+        scanline[..4].copy_from_slice(&header);
+        return file.read_exact(&mut scanline[4..]);
+    }
+    if ((header[2] as usize) << 8 | header[3] as usize) != width {
+        return Err(invalid("HDR scanline width mismatch."));
+    }
+
+    let mut component = vec![0u8; width];
+    for c in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            file.read_exact(&mut count)?;
+            let run = count[0] > 128;
+            let count = if run { count[0] - 128 } else { count[0] } as usize;
+            if count == 0 || x + count > width {
+                return Err(invalid("Corrupt HDR scanline."));
+            }
+            if run {
+                let mut value = [0u8; 1];
+                file.read_exact(&mut value)?;
+                component[x..x + count].fill(value[0]);
+            } else {
+                file.read_exact(&mut component[x..x + count])?;
+            }
+            x += count;
+        }
+        for (x, value) in component.iter().enumerate() {
+            scanline[4 * x + c] = *value;
+        }
+    }
+
+    Ok(())
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99,
+    115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025,
+    1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12,
+    12, 13, 13,
+];
+const WINDOW_SIZE: usize = 1 << 15;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+const STORED_BLOCK_SIZE: usize = 0xffff;
+
+// Deflate streams are packed starting from the least significant bit.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are defined most significant bit first.
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u16) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol as u32, 8),
+        144..=255 => writer.write_code(0x190 + (symbol - 144) as u32, 9),
+        256..=279 => writer.write_code((symbol - 256) as u32, 7),
+        _ => writer.write_code(0xc0 + (symbol - 280) as u32, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|&b| b as usize <= length)
+        .unwrap_or(0);
+    write_literal(writer, 257 + code as u16);
+    writer.write(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+
+    let code = DISTANCE_BASE
+        .iter()
+        .rposition(|&b| b as usize <= distance)
+        .unwrap_or(0);
+    writer.write_code(code as u32, 5);
+    writer.write(
+        (distance - DISTANCE_BASE[code] as usize) as u32,
+        DISTANCE_EXTRA[code] as u32,
+    );
+}
+
+// A single fixed Huffman block with greedy LZ77 matching over hash chains.
+fn deflate_fixed(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let hash = |i: usize| {
+        (((data[i] as usize) << 10)
+            ^ ((data[i + 1] as usize) << 5)
+            ^ data[i + 2] as usize)
+            & (WINDOW_SIZE - 1)
+    };
+    let mut head = vec![usize::MAX; WINDOW_SIZE];
+    let mut previous = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |i: usize, head: &mut Vec<usize>, previous: &mut Vec<usize>| {
+        if i + 2 < data.len() {
+            let h = hash(i);
+            previous[i % WINDOW_SIZE] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if i + 2 < data.len() {
+            let mut candidate = head[hash(i)];
+            let max_length = MAX_MATCH.min(data.len() - i);
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || i - candidate > WINDOW_SIZE - 1 {
+                    break;
+                }
+                let length = (0..max_length)
+                    .take_while(|&k| data[candidate + k] == data[i + k])
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = previous[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+            }
+        }
+
+        if best_length >= 3 {
+            write_match(&mut writer, best_length, best_distance);
+            for k in i..i + best_length {
+                insert(k, &mut head, &mut previous);
+            }
+            i += best_length;
+        } else {
+            write_literal(&mut writer, data[i] as u16);
+            insert(i, &mut head, &mut previous);
+            i += 1;
+        }
+    }
+
+    write_literal(&mut writer, 256);
+    writer.finish()
+}
+
+fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut bytes =
+        Vec::with_capacity(data.len() + 5 * (data.len() / STORED_BLOCK_SIZE + 1));
+    let mut chunks = data.chunks(STORED_BLOCK_SIZE).peekable();
+    if chunks.peek().is_none() {
+        bytes.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none() as u8;
+        let length = chunk.len() as u16;
+        bytes.push(last);
+        bytes.extend(length.to_le_bytes());
+        bytes.extend((!length).to_le_bytes());
+        bytes.extend(chunk);
+    }
+    bytes
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let fixed = deflate_fixed(data);
+    let body = if fixed.len() < data.len() + 5 {
+        fixed
+    } else {
+        deflate_stored(data)
+    };
+
+    let mut bytes = Vec::with_capacity(body.len() + 6);
+    bytes.extend([0x78, 0x01]);
+    bytes.extend(body);
+    bytes.extend(adler32(data).to_be_bytes());
+    bytes
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn write_chunk<W: Write>(
+    file: &mut W,
+    kind: &[u8; 4],
+    content: &[u8],
+) -> Result<(), Error> {
+    file.write_all(&(content.len() as u32).to_be_bytes())?;
+    file.write_all(kind)?;
+    file.write_all(content)?;
+    let mut crc = Vec::with_capacity(content.len() + 4);
+    crc.extend(kind);
+    crc.extend(content);
+    file.write_all(&crc32(&crc).to_be_bytes())
+}
+
+// 8-bit RGB, `rgb` holds three bytes per pixel in row order.
+pub fn write_png(
+    path: &str,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> Result<(), Error> {
+    // PNG has no empty images and stores both dimensions as positive i32.
+    let limit = 1..=i32::MAX as usize;
+    if !limit.contains(&width) || !limit.contains(&height) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Cannot write a {width}x{height} PNG."),
+        ));
+    }
+    if rgb.len() < 3 * width * height {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Not enough pixels for the PNG size.",
+        ));
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    header.extend([8, 2, 0, 0, 0]);
+    write_chunk(&mut file, b"IHDR", &header)?;
+
+    // Every scanline uses the `Sub` filter, which suits smooth renders well.
+    let mut filtered = Vec::with_capacity((3 * width + 1) * height);
+    for row in rgb.chunks_exact(3 * width).take(height) {
+        filtered.push(1);
+        for (i, &byte) in row.iter().enumerate() {
+            let left = if i >= 3 { row[i - 3] } else { 0 };
+            filtered.push(byte.wrapping_sub(left));
+        }
+    }
+    write_chunk(&mut file, b"IDAT", &zlib_compress(&filtered))?;
+    write_chunk(&mut file, b"IEND", &[])?;
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("aisth-{}-{name}", std::process::id()))
+    }
+
+    fn pixels(width: usize, height: usize) -> Vec<Vector3> {
+        (0..width * height)
+            .map(|i| {
+                let i = i as Float;
+                Vector3::new([i * 0.37, 1E-3 * (i + 1.0), 2.0_f64.powf(i - 6.0)])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rgbe_keeps_relative_precision() {
+        for v in pixels(8, 8) {
+            let decoded = from_rgbe(&to_rgbe(&v));
+            let max = v[0].max(v[1]).max(v[2]);
+            for c in 0..3 {
+                assert!((decoded[c] - v[c]).abs() <= max / 128.0);
+            }
+        }
+        assert_eq!(to_rgbe(&Vector3::zero()), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rgbe_handles_non_finite_values() {
+        let nan = to_rgbe(&Vector3::new([Float::NAN, 1.0, -2.0]));
+        assert_eq!(nan, to_rgbe(&Vector3::new([0.0, 1.0, 0.0])));
+
+        let largest = [255, 255, 255, 255];
+        assert_eq!(to_rgbe(&Vector3::new([Float::INFINITY; 3])), largest);
+        assert_eq!(to_rgbe(&Vector3::new([1E300; 3])), largest);
+        let inf = to_rgbe(&Vector3::new([Float::INFINITY, 0.0, 1.0]));
+        assert_eq!(inf, [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn hdr_round_trip() {
+        let (width, height) = (13, 5);
+        let data = pixels(width, height);
+        let path = temp_path("round-trip.hdr");
+        let path = path.to_str().unwrap();
+        write_hdr(path, width, height, &data).unwrap();
+        let (w, h, read) = read_hdr(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!((w, h), (width, height));
+        for (a, b) in data.iter().zip(&read) {
+            assert_eq!(from_rgbe(&to_rgbe(a)).v, b.v);
+        }
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let (width, height) = (7, 3);
+        let data = pixels(width, height);
+        let path = temp_path("round-trip.pfm");
+        let path = path.to_str().unwrap();
+        write_pfm(path, width, height, &data).unwrap();
+        let (w, h, read) = read_pfm(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!((w, h), (width, height));
+        for (a, b) in data.iter().zip(&read) {
+            for c in 0..3 {
+                assert_eq!(b[c], a[c] as f32 as Float);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_run_length_encoded_scanlines() {
+        let width = 10;
+        let mut encoded = vec![2, 2, 0, width as u8];
+        for c in 0..4u8 {
+            // A run of six followed by four literal values.
+            encoded.extend([128 + 6, c]);
+            encoded.extend([4, 10 + c, 20 + c, 30 + c, 40 + c]);
+        }
+
+        let mut scanline = vec![0u8; 4 * width];
+        read_scanline(&mut encoded.as_slice(), &mut scanline, width).unwrap();
+        assert_eq!(&scanline[..8], &[0, 1, 2, 3, 0, 1, 2, 3]);
+        assert_eq!(&scanline[36..], &[40, 41, 42, 43]);
+
+        encoded[4] = 128 + 11;
+        let result = read_scanline(&mut encoded.as_slice(), &mut scanline, width);
+        assert!(result.is_err());
+    }
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        bit: usize,
+    }
+
+    impl BitReader<'_> {
+        fn read(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for k in 0..count {
+                value |= ((self.data[self.bit / 8] >> (self.bit % 8)) as u32 & 1) << k;
+                self.bit += 1;
+            }
+            value
+        }
+
+        // Huffman codes are read most significant bit first.
+        fn read_code(&mut self, code: u32, count: u32) -> u32 {
+            (0..count).fold(code, |code, _| (code << 1) | self.read(1))
+        }
+    }
+
+    // Decoder for the fixed Huffman and stored blocks the encoder produces.
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut reader = BitReader { data, bit: 0 };
+        let mut out = Vec::new();
+        loop {
+            let last = reader.read(1);
+            match reader.read(2) {
+                0 => {
+                    reader.bit = reader.bit.next_multiple_of(8);
+                    let length = reader.read(16) as usize;
+                    assert_eq!(reader.read(16) as usize, !length & 0xffff);
+                    out.extend((0..length).map(|_| reader.read(8) as u8));
+                }
+                1 => loop {
+                    let symbol = match reader.read_code(0, 7) {
+                        c @ 0..=0x17 => c + 256,
+                        c => match reader.read_code(c, 1) {
+                            c @ 0x30..=0xbf => c - 0x30,
+                            c @ 0xc0..=0xc7 => c - 0xc0 + 280,
+                            c => reader.read_code(c, 1) - 0x190 + 144,
+                        },
+                    } as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let s = symbol - 257;
+                    let length = LENGTH_BASE[s] as usize
+                        + reader.read(LENGTH_EXTRA[s] as u32) as usize;
+                    let d = reader.read_code(0, 5) as usize;
+                    let distance = DISTANCE_BASE[d] as usize
+                        + reader.read(DISTANCE_EXTRA[d] as u32) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                },
+                kind => panic!("Unexpected block type {kind}."),
+            }
+            if last == 1 {
+                return out;
+            }
+        }
+    }
+
+    fn zlib_decompress(data: &[u8]) -> Vec<u8> {
+        assert_eq!((data[0] as u16 * 256 + data[1] as u16) % 31, 0);
+        let out = inflate(&data[2..data.len() - 4]);
+        assert_eq!(data[data.len() - 4..], adler32(&out).to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+    }
+
+    #[test]
+    fn zlib_round_trip() {
+        let mut rng = crate::lm::rng::Rng::new(1);
+        let noise = (0..70_000)
+            .map(|_| rng.uniform_u32() as u8)
+            .collect::<Vec<u8>>();
+        let repetitive = (0..50_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 1000) as u8)
+            .collect::<Vec<u8>>();
+
+        for data in [Vec::new(), b"a".to_vec(), vec![7; 1000], noise, repetitive] {
+            let compressed = zlib_compress(&data);
+            assert_eq!(zlib_decompress(&compressed), data);
+        }
+        assert!(zlib_compress(&[7; 1000]).len() < 50);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let (width, height) = (5, 4);
+        let rgb = (0..3 * width * height)
+            .map(|i| (i * 37 % 256) as u8)
+            .collect::<Vec<u8>>();
+        let path = temp_path("round-trip.png");
+        let path = path.to_str().unwrap();
+        write_png(path, width, height, &rgb).unwrap();
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(
+            bytes[..8],
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']
+        );
+        let mut chunks = Vec::new();
+        let mut rest = &bytes[8..];
+        while !rest.is_empty() {
+            let length =
+                u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let (kind, content) = (&rest[4..8], &rest[8..8 + length]);
+            let crc = &rest[8 + length..12 + length];
+            assert_eq!(crc, crc32(&rest[4..8 + length]).to_be_bytes());
+            chunks.push((kind.to_vec(), content.to_vec()));
+            rest = &rest[12 + length..];
+        }
+
+        let kinds = chunks
+            .iter()
+            .map(|c| c.0.as_slice())
+            .collect::<Vec<&[u8]>>();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 5, 0, 0, 0, 4, 8, 2, 0, 0, 0]);
+
+        let filtered = zlib_decompress(&chunks[1].1);
+        let mut decoded = Vec::new();
+        for row in filtered.chunks_exact(3 * width + 1) {
+            assert_eq!(row[0], 1);
+            let start = decoded.len();
+            for (i, &byte) in row[1..].iter().enumerate() {
+                let left = if i >= 3 { decoded[start + i - 3] } else { 0 };
+                decoded.push(byte.wrapping_add(left));
+            }
+        }
+        assert_eq!(decoded, rgb);
+    }
+
+    #[test]
+    fn png_rejects_empty_images() {
+        let path = temp_path("empty.png");
+        let path = path.to_str().unwrap();
+        assert!(write_png(path, 0, 4, &[]).is_err());
+        assert!(write_png(path, 4, 0, &[]).is_err());
+        assert!(write_png(path, 2, 2, &[0; 11]).is_err());
+        assert!(!std::path::Path::new(path).exists());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod image;
+pub mod parser;
+pub mod statistics;
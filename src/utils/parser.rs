@@ -0,0 +1,437 @@
+use crate::*;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Triangulation {
+    Fan,
+    EarClip,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObjVertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ObjGroup {
+    pub object: String,
+    pub name: String,
+    pub material: Option<String>,
+    pub triangles: Range<usize>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ObjModel {
+    pub positions: Vec<Vector3>,
+    pub uvs: Vec<Vector2>,
+    pub normals: Vec<Vector3>,
+    pub triangles: Vec<[ObjVertex; 3]>,
+    pub groups: Vec<ObjGroup>,
+    pub material_libraries: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ObjErrorKind {
+    Io(std::io::Error),
+    InvalidNumber(String),
+    MissingValue(&'static str),
+    InvalidIndex(String),
+    IndexOutOfRange(i64),
+    TooFewVertices(usize),
+}
+
+#[derive(Debug)]
+pub struct ObjError {
+    pub line: usize,
+    pub kind: ObjErrorKind,
+}
+
+impl ObjError {
+    fn new(line: usize, kind: ObjErrorKind) -> ObjError {
+        ObjError { line, kind }
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "line {}: ", self.line)?;
+        }
+        match &self.kind {
+            ObjErrorKind::Io(e) => write!(f, "{e}"),
+            ObjErrorKind::InvalidNumber(s) => write!(f, "invalid number `{s}`"),
+            ObjErrorKind::MissingValue(s) => write!(f, "missing {s}"),
+            ObjErrorKind::InvalidIndex(s) => write!(f, "invalid vertex reference `{s}`"),
+            ObjErrorKind::IndexOutOfRange(i) => write!(f, "index {i} is out of range"),
+            ObjErrorKind::TooFewVertices(n) => {
+                write!(f, "face has {n} vertices, at least 3 are required")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ObjErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub fn read_obj(path: &str, triangulation: Triangulation) -> Result<ObjModel, ObjError> {
+    let file = File::open(path).map_err(|e| ObjError::new(0, ObjErrorKind::Io(e)))?;
+    parse_obj(BufReader::new(file), triangulation)
+}
+
+pub fn parse_obj<R: BufRead>(
+    reader: R,
+    triangulation: Triangulation,
+) -> Result<ObjModel, ObjError> {
+    let mut model = ObjModel::default();
+    let mut object = String::new();
+    let mut group = String::new();
+    let mut material = None;
+    let mut start = 0;
+
+    for (i, line) in reader.lines().enumerate() {
+        let number = i + 1;
+        let line = line.map_err(|e| ObjError::new(number, ObjErrorKind::Io(e)))?;
+        let content = match line.split_once('#') {
+            Some((content, _)) => content,
+            None => &line,
+        };
+
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => {
+                let v = parse_floats::<3>(&mut tokens, number, "vertex coordinate")?;
+                model.positions.push(Vector3::new(v));
+            }
+            "vn" => {
+                let n = parse_floats::<3>(&mut tokens, number, "normal coordinate")?;
+                model.normals.push(Vector3::new(n));
+            }
+            "vt" => {
+                let u = parse_floats::<1>(&mut tokens, number, "texture coordinate")?;
+                let v = match tokens.next() {
+                    Some(t) => parse_float(t, number)?,
+                    None => 0.0,
+                };
+                model.uvs.push(Vector2::new([u[0], v]));
+            }
+            "f" => {
+                let face = tokens
+                    .map(|t| parse_vertex(t, &model, number))
+                    .collect::<Result<Vec<ObjVertex>, ObjError>>()?;
+                if face.len() < 3 {
+                    return Err(ObjError::new(
+                        number,
+                        ObjErrorKind::TooFewVertices(face.len()),
+                    ));
+                }
+                triangulate(&face, &model.positions, triangulation, &mut model.triangles);
+            }
+            "o" | "g" | "usemtl" => {
+                close_group(&mut model, &object, &group, &material, start);
+                start = model.triangles.len();
+                let name = tokens.collect::<Vec<&str>>().join(" ");
+                match keyword {
+                    "o" => {
+                        object = name;
+                        group.clear();
+                    }
+                    "g" => group = name,
+                    _ => material = Some(name),
+                }
+            }
+            "mtllib" => {
+                model
+                    .material_libraries
+                    .extend(tokens.map(|t| t.to_string()));
+            }
+            _ => (),
+        }
+    }
+
+    close_group(&mut model, &object, &group, &material, start);
+    Ok(model)
+}
+
+fn close_group(
+    model: &mut ObjModel,
+    object: &str,
+    group: &str,
+    material: &Option<String>,
+    start: usize,
+) {
+    if start == model.triangles.len() {
+        return;
+    }
+
+    model.groups.push(ObjGroup {
+        object: object.to_string(),
+        name: group.to_string(),
+        material: material.clone(),
+        triangles: start..model.triangles.len(),
+    });
+}
+
+fn parse_float(token: &str, line: usize) -> Result<Float, ObjError> {
+    token
+        .parse::<Float>()
+        .map_err(|_| ObjError::new(line, ObjErrorKind::InvalidNumber(token.to_string())))
+}
+
+fn parse_floats<'a, const N: usize>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    line: usize,
+    name: &'static str,
+) -> Result<[Float; N], ObjError> {
+    let mut v = [0.0; N];
+    for c in v.iter_mut() {
+        let token = tokens
+            .next()
+            .ok_or(ObjError::new(line, ObjErrorKind::MissingValue(name)))?;
+        *c = parse_float(token, line)?;
+    }
+    Ok(v)
+}
+
+// OBJ indices are one-based, negative indices count back from the latest element.
+fn resolve(token: &str, len: usize, line: usize) -> Result<usize, ObjError> {
+    let index = token.parse::<i64>().map_err(|_| {
+        ObjError::new(line, ObjErrorKind::InvalidIndex(token.to_string()))
+    })?;
+    let resolved = match index {
+        i if i > 0 => i - 1,
+        i if i < 0 => len as i64 + i,
+        _ => -1,
+    };
+
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(ObjError::new(line, ObjErrorKind::IndexOutOfRange(index)));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_vertex(
+    token: &str,
+    model: &ObjModel,
+    line: usize,
+) -> Result<ObjVertex, ObjError> {
+    let mut parts = token.split('/');
+    let position = match parts.next() {
+        Some(p) if !p.is_empty() => resolve(p, model.positions.len(), line)?,
+        _ => {
+            return Err(ObjError::new(
+                line,
+                ObjErrorKind::InvalidIndex(token.to_string()),
+            ))
+        }
+    };
+    let uv = match parts.next() {
+        Some(t) if !t.is_empty() => Some(resolve(t, model.uvs.len(), line)?),
+        _ => None,
+    };
+    let normal = match parts.next() {
+        Some(n) if !n.is_empty() => Some(resolve(n, model.normals.len(), line)?),
+        _ => None,
+    };
+    if parts.next().is_some() {
+        return Err(ObjError::new(
+            line,
+            ObjErrorKind::InvalidIndex(token.to_string()),
+        ));
+    }
+
+    Ok(ObjVertex {
+        position,
+        uv,
+        normal,
+    })
+}
+
+fn triangulate(
+    face: &[ObjVertex],
+    positions: &[Vector3],
+    triangulation: Triangulation,
+    triangles: &mut Vec<[ObjVertex; 3]>,
+) {
+    let mut remaining = (0..face.len()).collect::<Vec<usize>>();
+    if triangulation == Triangulation::EarClip && face.len() > 3 {
+        clip_ears(face, positions, &mut remaining, triangles);
+    }
+
+    for i in 1..remaining.len().saturating_sub(1) {
+        triangles.push([
+            face[remaining[0]],
+            face[remaining[i]],
+            face[remaining[i + 1]],
+        ]);
+    }
+}
+
+// Clips ears off the polygon projected onto the plane of its dominant normal axis.
+// Stops early on degenerate input and leaves the rest of `remaining` to the fan.
+fn clip_ears(
+    face: &[ObjVertex],
+    positions: &[Vector3],
+    remaining: &mut Vec<usize>,
+    triangles: &mut Vec<[ObjVertex; 3]>,
+) {
+    let mut normal = Vector3::zero();
+    for (i, v) in face.iter().enumerate() {
+        let p = positions[v.position];
+        let q = positions[face[(i + 1) % face.len()].position];
+        normal = normal + (p - q).cross(&(p + q)) * 0.5;
+    }
+    let axis = (0..3).fold(0, |a, i| {
+        if normal[i].abs() > normal[a].abs() {
+            i
+        } else {
+            a
+        }
+    });
+    if normal[axis] == 0.0 {
+        return;
+    }
+    let (x, y) = ((axis + 1) % 3, (axis + 2) % 3);
+    let orientation = normal[axis].signum();
+    let project = |i: usize| {
+        let p = positions[face[i].position];
+        Vector2::new([p[x], p[y]])
+    };
+    let area = |a: &Vector2, b: &Vector2, c: &Vector2| {
+        orientation * ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]))
+    };
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (
+                project(remaining[(i + n - 1) % n]),
+                project(remaining[i]),
+                project(remaining[(i + 1) % n]),
+            );
+            if area(&a, &b, &c) <= 0.0 {
+                return false;
+            }
+            (0..n)
+                .filter(|&j| j != i && j != (i + 1) % n && j != (i + n - 1) % n)
+                .all(|j| {
+                    let p = project(remaining[j]);
+                    area(&a, &b, &p) < 0.0
+                        || area(&b, &c, &p) < 0.0
+                        || area(&c, &a, &p) < 0.0
+                })
+        });
+
+        let i = match ear {
+            Some(i) => i,
+            None => return,
+        };
+        triangles.push([
+            face[remaining[(i + n - 1) % n]],
+            face[remaining[i]],
+            face[remaining[(i + 1) % n]],
+        ]);
+        remaining.remove(i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str, triangulation: Triangulation) -> Result<ObjModel, ObjError> {
+        parse_obj(source.as_bytes(), triangulation)
+    }
+
+    #[test]
+    fn parses_vertex_references() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0 # corner\nvt 0.5\nvn 0 0 1\n\nf 1/1/1 2//1 -1\n",
+            Triangulation::Fan,
+        )
+        .unwrap();
+
+        assert_eq!(model.positions.len(), 3);
+        assert_eq!(model.uvs[0].v, [0.5, 0.0]);
+        let [a, b, c] = model.triangles[0];
+        assert_eq!((a.position, a.uv, a.normal), (0, Some(0), Some(0)));
+        assert_eq!((b.position, b.uv, b.normal), (1, None, Some(0)));
+        assert_eq!((c.position, c.uv, c.normal), (2, None, None));
+    }
+
+    #[test]
+    fn groups_triangles() {
+        let model = parse(
+            "mtllib a.mtl b.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+             o box\ng top\nusemtl red\nf 1 2 3\nf 2 4 3\ng bottom\nf 1 3 2\n",
+            Triangulation::Fan,
+        )
+        .unwrap();
+
+        assert_eq!(model.material_libraries, ["a.mtl", "b.mtl"]);
+        assert_eq!(model.groups.len(), 2);
+        let (top, bottom) = (&model.groups[0], &model.groups[1]);
+        assert_eq!((top.object.as_str(), top.name.as_str()), ("box", "top"));
+        assert_eq!(top.material.as_deref(), Some("red"));
+        assert_eq!(top.triangles, 0..2);
+        assert_eq!(bottom.name, "bottom");
+        assert_eq!(bottom.triangles, 2..3);
+    }
+
+    #[test]
+    fn reports_errors_with_line() {
+        let error = |source| parse(source, Triangulation::Fan).unwrap_err();
+
+        let e = error("v 0 0 0\nv 0 x 0\n");
+        assert_eq!(e.line, 2);
+        assert!(matches!(e.kind, ObjErrorKind::InvalidNumber(ref s) if s == "x"));
+        let e = error("v 0 0\n");
+        assert!(matches!(e.kind, ObjErrorKind::MissingValue(_)));
+        let e = error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n");
+        assert_eq!(e.line, 4);
+        assert!(matches!(e.kind, ObjErrorKind::IndexOutOfRange(4)));
+        let e = error("v 0 0 0\nf 1 0 1\n");
+        assert!(matches!(e.kind, ObjErrorKind::IndexOutOfRange(0)));
+        let e = error("v 0 0 0\nf 1 1/// 1\n");
+        assert!(matches!(e.kind, ObjErrorKind::InvalidIndex(_)));
+        let e = error("v 0 0 0\nf 1 1\n");
+        assert!(matches!(e.kind, ObjErrorKind::TooFewVertices(2)));
+    }
+
+    #[test]
+    fn ear_clipping_keeps_concave_faces_inside() {
+        // An L shape whose fan from the first vertex would leave the polygon.
+        let source = "v 1 1 0\nv 2 1 0\nv 2 2 0\nv 0 2 0\nv 0 0 0\nv 1 0 0\n\
+                      f 1 2 3 4 5 6\n";
+        let model = parse(source, Triangulation::EarClip).unwrap();
+        assert_eq!(model.triangles.len(), 4);
+
+        let mut area = 0.0;
+        for [a, b, c] in &model.triangles {
+            let (a, b, c) = (
+                model.positions[a.position],
+                model.positions[b.position],
+                model.positions[c.position],
+            );
+            let z = (b - a).cross(&(c - a))[2];
+            assert!(z > 0.0);
+            area += 0.5 * z;
+        }
+        assert_eq!(area, 3.0);
+    }
+}
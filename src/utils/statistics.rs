@@ -0,0 +1,80 @@
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const BAR_WIDTH: usize = 30;
+
+// Counts finished work items from any number of threads. The bar is only drawn
+// when stderr is a terminal, so redirected output stays clean.
+pub struct Progress {
+    label: String,
+    total: usize,
+    done: AtomicUsize,
+    start: Instant,
+    visible: bool,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: usize) -> Progress {
+        Progress {
+            label: label.to_string(),
+            total,
+            done: AtomicUsize::new(0),
+            start: Instant::now(),
+            visible: std::io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn update(&self, count: usize) {
+        let done = self.done.fetch_add(count, Ordering::Relaxed) + count;
+        self.draw(done);
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> f64 {
+        self.start.elapsed().as_secs_f64()
+    }
+
+    pub fn finish(&self) {
+        if self.visible {
+            self.draw(self.done());
+            eprintln!();
+        }
+    }
+
+    fn draw(&self, done: usize) {
+        if !self.visible {
+            return;
+        }
+
+        let fraction = if self.total == 0 {
+            1.0
+        } else {
+            done.min(self.total) as f64 / self.total as f64
+        };
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let elapsed = self.elapsed();
+        let remaining = if fraction > 0.0 {
+            elapsed / fraction - elapsed
+        } else {
+            0.0
+        };
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r{} [{}{}] {:3.0}% ({}/{}) {:.1}s, {:.1}s left ",
+            self.label,
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            fraction * 100.0,
+            done,
+            self.total,
+            elapsed,
+            remaining,
+        );
+        let _ = stderr.flush();
+    }
+}